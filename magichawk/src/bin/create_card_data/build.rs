@@ -0,0 +1,165 @@
+use clap::Args;
+use magichawk::{
+    CardDataFormat, CardPrintings, MinimalScryfallObject, SCRYFALL_API_BASE, SCRYFALL_IMAGE_BASE,
+    ScryfallCardNames, ScryfallEndpoints, ScryfallObject, insert_extra_object, is_card, is_extra,
+    read_bulk_file,
+};
+use std::collections::HashSet;
+
+use crate::{Outcome, Progress, write_card_data, write_json};
+
+/// Cards are told apart from tokens, emblems and other helper cards, which are stored as extras,
+/// by the layout, set type and type line of the objects, optionally the card names from the
+/// Scryfall API are compared with the result.
+#[derive(Args, Debug)]
+pub struct BuildArgs {
+    /// bulk "Default Cards" file, can be gzip compressed
+    input: String,
+    /// card data file to write
+    #[arg(short, long)]
+    output: String,
+    /// format of the card data: json, binary (smaller and faster to load) or compressed (binary)
+    #[arg(long, default_value_t = CardDataFormat::Json)]
+    format: CardDataFormat,
+    /// write the objects with images which couldn't be converted to this file
+    #[arg(long)]
+    unconverted: Option<String>,
+    /// write the objects which are neither cards nor tokens, emblems or other helper cards to
+    /// this file
+    #[arg(long)]
+    not_cards: Option<String>,
+    /// compare the card names with the card names catalog of the Scryfall API
+    #[arg(long)]
+    check_catalog: bool,
+    /// base url of the scryfall API, e.g. of a mirror
+    #[arg(long, default_value = SCRYFALL_API_BASE)]
+    scryfall_api_url: String,
+}
+
+const MAX_LISTED_NAMES: usize = 10;
+
+fn list_names(description: &str, names: &[&String]) {
+    println!("{} {}", names.len(), description);
+    for name in names.iter().take(MAX_LISTED_NAMES) {
+        println!("    {}", name);
+    }
+    if names.len() > MAX_LISTED_NAMES {
+        println!("    ...");
+    }
+}
+
+// only a report, the card data doesn't depend on the catalog
+fn check_catalog(card_data: &CardPrintings, scryfall_api_url: &str) {
+    let endpoints = ScryfallEndpoints::new(scryfall_api_url, SCRYFALL_IMAGE_BASE);
+    let catalog = match ScryfallCardNames::from_api_call_blocking(&endpoints) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            println!("couldn't get the card names catalog to compare: {}", e);
+            return;
+        }
+    };
+    let catalog_names: HashSet<&String> = catalog.names.iter().collect();
+    let mut not_in_catalog: Vec<&String> = card_data
+        .printings
+        .keys()
+        .filter(|name| !catalog_names.contains(name))
+        .collect();
+    not_in_catalog.sort();
+    let mut not_in_bulk_data: Vec<&String> = catalog
+        .names
+        .iter()
+        .filter(|name| !card_data.printings.contains_key(*name))
+        .collect();
+    not_in_bulk_data.sort();
+    println!(
+        "compared with the {} names in the card names catalog:",
+        catalog.names.len()
+    );
+    list_names("card names aren't in the catalog", &not_in_catalog);
+    list_names(
+        "names in the catalog don't have printings in the bulk data",
+        &not_in_bulk_data,
+    );
+}
+
+pub fn run(args: BuildArgs, dry_run: bool) -> Outcome {
+    // only the objects for the diagnostic files which were asked for are kept
+    let mut unconverted: Vec<ScryfallObject> = Vec::new();
+    let mut unconverted_count = 0;
+    let mut not_cards: Vec<ScryfallObject> = Vec::new();
+    let mut not_cards_count = 0;
+
+    // the bulk data is streamed, only the objects which are kept are in memory at once
+    let mut card_data = CardPrintings::new();
+    let mut progress = Progress::new("objects read", 10000);
+    let entries = read_bulk_file(&args.input, |default_card| {
+        progress.step();
+        match MinimalScryfallObject::from_dict(&default_card) {
+            Some(scryfall_object) => {
+                if is_card(&default_card) {
+                    let language = scryfall_object.language.clone();
+                    card_data
+                        .printings
+                        .entry(scryfall_object.name.clone())
+                        .or_default()
+                        .push(scryfall_object);
+                    card_data.languages.insert(language);
+                } else if is_extra(&default_card) {
+                    insert_extra_object(&mut card_data, &default_card);
+                } else {
+                    not_cards_count += 1;
+                    if args.not_cards.is_some() {
+                        not_cards.push(default_card);
+                    }
+                }
+            }
+            None => {
+                unconverted_count += 1;
+                if args.unconverted.is_some() && default_card["image_status"] != "missing" {
+                    unconverted.push(default_card);
+                }
+            }
+        }
+    })
+    .map_err(|e| format!("couldn't read bulk data from {}: {}", args.input, e))?;
+    println!("there are {} entries in {}", entries, args.input);
+
+    let different_cards: usize = card_data
+        .printings
+        .values()
+        .map(|printings| printings.len())
+        .sum();
+    println!(
+        "there are {} card names and {} (card name, set) combinations ({} languages)",
+        card_data.printings.len(),
+        different_cards,
+        card_data.languages.len(),
+    );
+    println!(
+        "there are {} names of tokens, emblems and other helper cards",
+        card_data.extras.len(),
+    );
+    println!(
+        "there are {} objects which couldn't be converted and {} objects which are neither \
+         cards nor extras",
+        unconverted_count, not_cards_count
+    );
+
+    if args.check_catalog {
+        check_catalog(&card_data, &args.scryfall_api_url);
+    }
+
+    write_card_data(&card_data, &args.output, args.format, dry_run)?;
+    if let Some(path) = &args.unconverted {
+        println!(
+            "{} objects which couldn't be converted have image data, saved in {}",
+            unconverted.len(),
+            path
+        );
+        write_json(&unconverted, path, dry_run)?;
+    }
+    if let Some(path) = &args.not_cards {
+        write_json(&not_cards, path, dry_run)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,131 @@
+use clap::{Parser, Subcommand};
+use magichawk::{CardDataFormat, CardPrintings};
+use std::fs::File;
+use std::io::BufWriter;
+use std::process::ExitCode;
+
+mod build;
+mod prefetch;
+mod update;
+mod validate;
+
+/// Create and maintain card data files for magichawk from the bulk data of the Scryfall API, see
+/// https://scryfall.com/docs/api/bulk-data.
+///
+/// Exits with 1 on errors and with 3 when the card data has problems or images are still missing.
+#[derive(Parser, Debug)]
+struct Cli {
+    /// only report what would be done, don't write files or download images
+    #[arg(long, global = true)]
+    dry_run: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Build card data from a bulk "Default Cards" file
+    Build(build::BuildArgs),
+    /// Merge newer cards into existing card data
+    Update(update::UpdateArgs),
+    /// Check card data for problems
+    Validate(validate::ValidateArgs),
+    /// Download the images of the cards in card data into an image directory
+    Prefetch(prefetch::PrefetchArgs),
+    /// Count the printings in card data per set and per language
+    Stats(validate::StatsArgs),
+}
+
+/// Why a subcommand didn't succeed, which decides the exit code.
+enum Failure {
+    Error(String),
+    /// the command ran but found problems or couldn't finish everything
+    Problems,
+}
+
+impl From<String> for Failure {
+    fn from(e: String) -> Self {
+        Failure::Error(e)
+    }
+}
+
+type Outcome = Result<(), Failure>;
+
+const EXIT_PROBLEMS: u8 = 3;
+
+/// Reports every `every` steps of a long running loop on stderr.
+struct Progress {
+    label: &'static str,
+    every: usize,
+    done: usize,
+}
+
+impl Progress {
+    fn new(label: &'static str, every: usize) -> Progress {
+        Progress {
+            label,
+            every,
+            done: 0,
+        }
+    }
+
+    fn step(&mut self) {
+        self.done += 1;
+        if self.done.is_multiple_of(self.every) {
+            eprintln!("{} {}", self.done, self.label);
+        }
+    }
+}
+
+fn read_card_data(path: &str) -> Result<CardPrintings, Failure> {
+    CardPrintings::from_file(path)
+        .map_err(|e| Failure::Error(format!("couldn't read card data from {}: {}", path, e)))
+}
+
+fn write_card_data(
+    printings: &CardPrintings,
+    path: &str,
+    format: CardDataFormat,
+    dry_run: bool,
+) -> Outcome {
+    if dry_run {
+        println!("dry run, not writing {} card data to {}", format, path);
+        return Ok(());
+    }
+    let file = File::create(path).map_err(|e| format!("couldn't create {}: {}", path, e))?;
+    printings
+        .write_to(BufWriter::new(file), format)
+        .map_err(|e| format!("couldn't write {}: {}", path, e))?;
+    println!("wrote {} card data to {}", format, path);
+    Ok(())
+}
+
+fn write_json<T: serde::Serialize>(value: &T, path: &str, dry_run: bool) -> Outcome {
+    if dry_run {
+        println!("dry run, not writing {}", path);
+        return Ok(());
+    }
+    let file = File::create(path).map_err(|e| format!("couldn't create {}: {}", path, e))?;
+    serde_json::to_writer(BufWriter::new(file), value)
+        .map_err(|e| format!("couldn't write {}: {}", path, e))?;
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let outcome = match cli.command {
+        Command::Build(args) => build::run(args, cli.dry_run),
+        Command::Update(args) => update::run(args, cli.dry_run),
+        Command::Validate(args) => validate::run(args),
+        Command::Prefetch(args) => prefetch::run(args, cli.dry_run),
+        Command::Stats(args) => validate::run_stats(args),
+    };
+    match outcome {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::Error(e)) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+        Err(Failure::Problems) => ExitCode::from(EXIT_PROBLEMS),
+    }
+}
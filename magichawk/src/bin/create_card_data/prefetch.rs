@@ -0,0 +1,176 @@
+use clap::Args;
+use magichawk::{
+    CardData, ImageResponse, ImageSource, ImageStore, MinimalScryfallObject, SCRYFALL_API_BASE,
+    SCRYFALL_IMAGE_BASE, ScryfallCardNames, ScryfallClient, ScryfallEndpoints, Validators,
+};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use crate::{Failure, Outcome, Progress, read_card_data};
+
+/// E.g. to seed the `image_dir` of an offline instance. Images which are already in the directory
+/// are skipped, so an interrupted run picks up where it stopped when started again.
+#[derive(Args, Debug)]
+pub struct PrefetchArgs {
+    /// card data file, json or binary
+    card_data: String,
+    /// image directory
+    image_dir: String,
+    /// only the cards of this decklist, in all of their printings
+    #[arg(long)]
+    decklist: Option<String>,
+    /// only printings of this set, can be given more than once
+    #[arg(long = "set")]
+    sets: Vec<String>,
+    /// also tokens, emblems and other helper cards
+    #[arg(long)]
+    extras: bool,
+    /// minimum time between calls to scryfall
+    #[arg(long, default_value_t = 100)]
+    interval_ms: u64,
+    /// base url of the scryfall image host, e.g. of a mirror
+    #[arg(long, default_value = SCRYFALL_IMAGE_BASE)]
+    scryfall_image_url: String,
+}
+
+// stop instead of counting every remaining image as failed when scryfall can't be reached
+const MAX_FAILURES_IN_A_ROW: usize = 10;
+
+// the names of the decklist entries and the meld results they can turn into
+fn decklist_names(card_data: &CardData, decklist: &str) -> (BTreeSet<String>, BTreeSet<String>) {
+    let mut names = BTreeSet::new();
+    let mut extras = BTreeSet::new();
+    for line in magichawk::parse_decklist(decklist, &card_data.printings.languages) {
+        let Some(entry) = line.as_entry() else {
+            continue;
+        };
+        let (lookup, found) = if entry.extra {
+            (&card_data.extras_lookup, &mut extras)
+        } else {
+            (&card_data.lookup, &mut names)
+        };
+        match lookup.find(&entry.name) {
+            Some(result) => {
+                found.insert(result.name);
+            }
+            None => println!("no card data for {}", entry.name),
+        }
+    }
+    let meld_results: Vec<String> = names
+        .iter()
+        .filter_map(|name| card_data.printings.printings.get(name))
+        .flatten()
+        .filter_map(|printing| printing.meld_result.clone())
+        .collect();
+    names.extend(meld_results.into_iter().map(|name| name.to_lowercase()));
+    (names, extras)
+}
+
+fn image_uris<'a, I>(printings: I, sets: &[String]) -> impl Iterator<Item = String>
+where
+    I: Iterator<Item = &'a MinimalScryfallObject>,
+{
+    printings
+        .filter(|printing| sets.is_empty() || sets.contains(&printing.set))
+        .flat_map(|printing| printing.faces.iter().map(|face| face.border_crop.clone()))
+}
+
+pub fn run(args: PrefetchArgs, dry_run: bool) -> Outcome {
+    let printings = read_card_data(&args.card_data)?;
+    let store = ImageStore::new(&args.image_dir)
+        .map_err(|e| format!("couldn't open image directory {}: {}", args.image_dir, e))?;
+    let sets: Vec<String> = args.sets.iter().map(|set| set.to_lowercase()).collect();
+
+    let card_names = ScryfallCardNames::from_printings(&printings);
+    let card_data = CardData::with_card_names(printings, card_names);
+    let (names, extras) = match &args.decklist {
+        Some(decklist) => {
+            let decklist = std::fs::read_to_string(decklist)
+                .map_err(|e| format!("couldn't read decklist {}: {}", decklist, e))?;
+            decklist_names(&card_data, &decklist)
+        }
+        None => (
+            card_data.printings.printings.keys().cloned().collect(),
+            if args.extras {
+                card_data.printings.extras.keys().cloned().collect()
+            } else {
+                BTreeSet::new()
+            },
+        ),
+    };
+
+    let card_printings = names
+        .iter()
+        .filter_map(|name| card_data.printings.printings.get(name))
+        .flatten();
+    let extra_printings = extras
+        .iter()
+        .filter_map(|name| card_data.printings.extras.get(name))
+        .flatten();
+    let uris: BTreeSet<String> = image_uris(card_printings.chain(extra_printings), &sets).collect();
+    let missing: Vec<&String> = uris.iter().filter(|uri| !store.contains(uri)).collect();
+    println!(
+        "{} images for {} names, {} of them are already in {}",
+        uris.len(),
+        names.len() + extras.len(),
+        uris.len() - missing.len(),
+        args.image_dir
+    );
+    if dry_run {
+        println!("dry run, not downloading {} images", missing.len());
+        return Ok(());
+    }
+
+    magichawk::scryfall_rate_limiter().set_interval(Duration::from_millis(args.interval_ms));
+    let client = ScryfallClient::new().with_endpoints(ScryfallEndpoints::new(
+        SCRYFALL_API_BASE,
+        &args.scryfall_image_url,
+    ));
+    let (downloaded, failed) = rocket::execute(async {
+        let mut downloaded = 0;
+        let mut failed = 0;
+        let mut failures_in_a_row = 0;
+        let mut progress = Progress::new("images done", 100);
+        for uri in missing.iter() {
+            let error = match client.image(uri, &Validators::default()).await {
+                Ok(ImageResponse::Modified { bytes, validators }) => store
+                    .store(uri, &bytes, &validators)
+                    .err()
+                    .map(|e| format!("couldn't store it: {}", e)),
+                // the request wasn't conditional, so there is no image to keep
+                Ok(ImageResponse::NotModified) => Some("unexpected 304 not modified".to_string()),
+                Err(e) => Some(e.to_string()),
+            };
+            match error {
+                None => {
+                    downloaded += 1;
+                    failures_in_a_row = 0;
+                }
+                Some(e) => {
+                    eprintln!("couldn't prefetch {}: {}", uri, e);
+                    failed += 1;
+                    failures_in_a_row += 1;
+                    if failures_in_a_row == MAX_FAILURES_IN_A_ROW {
+                        eprintln!("giving up after {} failures in a row", failures_in_a_row);
+                        break;
+                    }
+                }
+            }
+            progress.step();
+        }
+        (downloaded, failed)
+    });
+
+    println!(
+        "downloaded {} images, {} failed, {} still missing",
+        downloaded,
+        failed,
+        missing.len() - downloaded
+    );
+    if downloaded == missing.len() {
+        Ok(())
+    } else {
+        println!("run again to download the missing images");
+        Err(Failure::Problems)
+    }
+}
@@ -0,0 +1,111 @@
+use clap::Args;
+use magichawk::{
+    CardDataFormat, CardDataMerge, MinimalScryfallObject, SCRYFALL_API_BASE, SCRYFALL_IMAGE_BASE,
+    ScryfallClient, ScryfallEndpoints, query_scryfall_released_since, read_bulk_file,
+};
+use time::{Date, Month};
+
+use crate::{Failure, Outcome, Progress, read_card_data, write_card_data};
+
+/// Instead of building the card data from scratch, merge a newer bulk "Default Cards" file or the
+/// cards released since a date according to the Scryfall API into it. Printings are matched by
+/// their Scryfall id.
+#[derive(Args, Debug)]
+pub struct UpdateArgs {
+    /// existing card data file, json or binary
+    card_data: String,
+    /// card data file to write, the existing one if not given
+    #[arg(short, long)]
+    output: Option<String>,
+    /// newer bulk "Default Cards" file, can be gzip compressed, printings which aren't in it are
+    /// removed
+    #[arg(long, conflicts_with = "since", required_unless_present = "since")]
+    bulk: Option<String>,
+    /// cards released on or after this date (YYYY-MM-DD) according to the Scryfall API
+    #[arg(long, value_parser = parse_date)]
+    since: Option<Date>,
+    /// format of the card data: json, binary or compressed (binary)
+    #[arg(long, default_value_t = CardDataFormat::Json)]
+    format: CardDataFormat,
+    /// base url of the scryfall API, e.g. of a mirror
+    #[arg(long, default_value = SCRYFALL_API_BASE)]
+    scryfall_api_url: String,
+}
+
+fn parse_date(s: &str) -> Result<Date, String> {
+    let invalid = || format!("{} isn't a date like 2023-02-17", s);
+    let parts: Vec<&str> = s.split('-').collect();
+    let [year, month, day] = parts[..] else {
+        return Err(invalid());
+    };
+    let year: i32 = year.parse().map_err(|_| invalid())?;
+    let month: u8 = month.parse().map_err(|_| invalid())?;
+    let day: u8 = day.parse().map_err(|_| invalid())?;
+    let month = Month::try_from(month).map_err(|_| invalid())?;
+    Date::from_calendar_date(year, month, day).map_err(|_| invalid())
+}
+
+const MAX_LISTED_PRINTINGS: usize = 20;
+
+fn list_printings(description: &str, printings: &[MinimalScryfallObject]) {
+    if printings.is_empty() {
+        return;
+    }
+    println!("{}:", description);
+    for printing in printings.iter().take(MAX_LISTED_PRINTINGS) {
+        println!(
+            "    {} ({} {})",
+            printing.name,
+            printing.set,
+            printing.collector_number.as_deref().unwrap_or_default()
+        );
+    }
+    if printings.len() > MAX_LISTED_PRINTINGS {
+        println!(
+            "    ... and {} more",
+            printings.len() - MAX_LISTED_PRINTINGS
+        );
+    }
+}
+
+pub fn run(args: UpdateArgs, dry_run: bool) -> Outcome {
+    let mut merge = CardDataMerge::new(read_card_data(&args.card_data)?);
+
+    let merged = match (&args.bulk, args.since) {
+        (Some(bulk), _) => {
+            let mut progress = Progress::new("objects merged", 10000);
+            read_bulk_file(bulk, |object| {
+                progress.step();
+                merge.insert(&object);
+            })
+        }
+        (None, Some(since)) => {
+            let client = ScryfallClient::new().with_endpoints(ScryfallEndpoints::new(
+                &args.scryfall_api_url,
+                SCRYFALL_IMAGE_BASE,
+            ));
+            rocket::execute(query_scryfall_released_since(since, &client)).map(|objects| {
+                for object in objects.iter() {
+                    merge.insert(object);
+                }
+                objects.len()
+            })
+        }
+        (None, None) => {
+            return Err(Failure::Error(
+                "either --bulk or --since is needed".to_string(),
+            ));
+        }
+    }
+    .map_err(|e| format!("couldn't get the newer cards: {}", e))?;
+
+    // only a complete bulk file tells which printings don't exist any more
+    let (printings, report) = merge.finish(args.bulk.is_some());
+    println!("merged {} scryfall objects: {}", merged, report);
+    list_printings("added", &report.added);
+    list_printings("changed", &report.changed);
+    list_printings("removed", &report.removed);
+
+    let output = args.output.as_ref().unwrap_or(&args.card_data);
+    write_card_data(&printings, output, args.format, dry_run)
+}
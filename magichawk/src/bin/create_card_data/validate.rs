@@ -0,0 +1,69 @@
+use clap::Args;
+use std::collections::BTreeMap;
+
+use crate::{Failure, Outcome, read_card_data};
+
+/// Checks for duplicate printings, missing back faces, meld results without printings, unknown
+/// languages and invalid image uris.
+#[derive(Args, Debug)]
+pub struct ValidateArgs {
+    /// card data file, json or binary
+    card_data: String,
+    /// how many problems of each kind are listed
+    #[arg(long, default_value_t = 10)]
+    max_listed: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    /// card data file, json or binary
+    card_data: String,
+}
+
+pub fn run(args: ValidateArgs) -> Outcome {
+    let printings = read_card_data(&args.card_data)?;
+    let problems = printings.validate();
+    let mut by_kind: BTreeMap<&str, Vec<_>> = BTreeMap::new();
+    for problem in problems.iter() {
+        by_kind.entry(problem.kind()).or_default().push(problem);
+    }
+    for (kind, problems) in by_kind.iter() {
+        println!("{} problems of the kind {}:", problems.len(), kind);
+        for problem in problems.iter().take(args.max_listed) {
+            println!("    {}", problem);
+        }
+        if problems.len() > args.max_listed {
+            println!("    ...");
+        }
+    }
+    if problems.is_empty() {
+        println!("no problems found in {}", args.card_data);
+        Ok(())
+    } else {
+        Err(Failure::Problems)
+    }
+}
+
+fn print_counts(description: &str, counts: &BTreeMap<String, usize>) {
+    println!("{}:", description);
+    let mut by_count: Vec<(&String, &usize)> = counts.iter().collect();
+    by_count.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    for (key, count) in by_count {
+        println!("    {:>8} {}", count, key);
+    }
+}
+
+pub fn run_stats(args: StatsArgs) -> Outcome {
+    let stats = read_card_data(&args.card_data)?.stats();
+    println!(
+        "{} card names with {} printings, {} names of extras with {} printings in {} sets",
+        stats.names,
+        stats.printings,
+        stats.extra_names,
+        stats.extra_printings,
+        stats.by_set.len()
+    );
+    print_counts("printings per set", &stats.by_set);
+    print_counts("printings per language", &stats.by_language);
+    Ok(())
+}
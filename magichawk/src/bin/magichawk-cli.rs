@@ -0,0 +1,226 @@
+use clap::{Parser, ValueEnum};
+use magichawk::{
+    BacksideMode, BulkDataSource, CardData, CardPrintings, CardSource, ExportFile, ImageFileFormat,
+    ImageSource, ImageStore, MeldMode, PaperSize, PrintServicePreset, PrintingPolicy, ProxyOptions,
+    SCRYFALL_API_BASE, SCRYFALL_IMAGE_BASE, ScryfallCache, ScryfallCardNames, ScryfallClient,
+    ScryfallEndpoints, SharedImageCache,
+};
+use std::io::Read;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+/// Create proxy sheets from a decklist without the web server, with the same decklist format and
+/// options as the web page. Cards and images come from Scryfall unless card data, bulk data or an
+/// image directory are given.
+#[derive(Parser, Debug)]
+struct Opts {
+    /// decklist file, read from stdin if not given or -
+    decklist: Option<String>,
+    /// pdf file to write, or for images the directory or with --zip the zip file
+    #[arg(short, long)]
+    output: String,
+    /// write images instead of a pdf: png or jpeg
+    #[arg(long, value_enum)]
+    format: Option<ImageFormat>,
+    /// write the images into a zip file instead of a directory
+    #[arg(long, requires = "format")]
+    zip: bool,
+    /// one image per card, e.g. to upload to online print services, instead of one per page
+    #[arg(long, requires = "format")]
+    cards: bool,
+    /// resolution of the images, by default the resolution of the card images (about 196 dpi)
+    #[arg(long, requires = "format")]
+    dpi: Option<u32>,
+    /// one image per card at the size of a print service with bleed, and a manifest of how often
+    /// each is printed: mpc or WIDTHxHEIGHT+BLEED in pixels
+    #[arg(long, requires = "format", conflicts_with_all = ["cards", "dpi"])]
+    print_service: Option<PrintServicePreset>,
+    /// how many backsides of double faced cards are printed: zero, one, matching or back-only
+    #[arg(long, default_value = "zero")]
+    backside: BacksideMode,
+    /// how the backsides of meld cards are printed: whole or halves
+    #[arg(long, default_value = "whole")]
+    meld: MeldMode,
+    /// paper size of the pdf: a4 or letter
+    #[arg(long, default_value_t = PaperSize::A4, conflicts_with = "format")]
+    paper: PaperSize,
+    /// also use printings which only exist digitally
+    #[arg(long)]
+    digital: bool,
+    /// also print the tokens, emblems and other helper cards the cards create
+    #[arg(long)]
+    tokens: bool,
+    /// card data file as written by create_card_data, json or binary
+    #[arg(long)]
+    card_data: Option<String>,
+    /// take the cards from this bulk data file or directory instead of scryfall
+    #[arg(long)]
+    bulk_data: Option<String>,
+    /// take the images from this directory instead of scryfall, see create_card_data prefetch
+    #[arg(long)]
+    image_dir: Option<String>,
+    /// keep downloaded images in this directory for later runs
+    #[arg(long)]
+    image_cache_dir: Option<String>,
+    /// base url of the scryfall API, e.g. of a mirror
+    #[arg(long, default_value = SCRYFALL_API_BASE)]
+    scryfall_api_url: String,
+    /// base url of the scryfall image host, e.g. of a mirror
+    #[arg(long, default_value = SCRYFALL_IMAGE_BASE)]
+    scryfall_image_url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ImageFormat {
+    Png,
+    Jpeg,
+}
+
+fn read_decklist(path: Option<&str>) -> Result<String, String> {
+    match path {
+        None | Some("-") => {
+            let mut decklist = String::new();
+            std::io::stdin()
+                .read_to_string(&mut decklist)
+                .map_err(|e| format!("couldn't read decklist from stdin: {}", e))?;
+            Ok(decklist)
+        }
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read decklist {}: {}", path, e)),
+    }
+}
+
+type Sources = (Box<dyn CardSource>, Box<dyn ImageSource>);
+
+fn create_sources(opts: &Opts) -> Result<Sources, String> {
+    let client = Arc::new(ScryfallClient::new().with_endpoints(ScryfallEndpoints::new(
+        &opts.scryfall_api_url,
+        &opts.scryfall_image_url,
+    )));
+    let card_source: Box<dyn CardSource> = match &opts.bulk_data {
+        Some(path) => Box::new(
+            BulkDataSource::from_path(path)
+                .map_err(|e| format!("couldn't read bulk data {}: {}", path, e))?,
+        ),
+        None => Box::new(client.clone()),
+    };
+    let image_source: Box<dyn ImageSource> = match &opts.image_dir {
+        Some(dir) => Box::new(
+            ImageStore::new(dir)
+                .map_err(|e| format!("couldn't open image directory {}: {}", dir, e))?,
+        ),
+        None => Box::new(client),
+    };
+    Ok((card_source, image_source))
+}
+
+async fn load_card_data(opts: &Opts, source: &dyn CardSource) -> Result<CardData, String> {
+    let printings = match &opts.card_data {
+        Some(path) => CardPrintings::from_file(path)
+            .map_err(|e| format!("couldn't read card data from {}: {}", path, e))?,
+        None => CardPrintings::new(),
+    };
+    // printings of further cards are looked up in the card source when asked for
+    let card_names = match source.card_names().await {
+        Ok(card_names) => card_names,
+        Err(e) if opts.card_data.is_some() => {
+            eprintln!(
+                "couldn't get card names, using the names in the card data: {}",
+                e
+            );
+            ScryfallCardNames::from_printings(&printings)
+        }
+        Err(e) => return Err(format!("couldn't get card names: {}", e)),
+    };
+    Ok(CardData::with_card_names(printings, card_names))
+}
+
+fn write_images(files: &[ExportFile], opts: &Opts) -> Result<(), String> {
+    if opts.zip {
+        let zip = magichawk::files_to_zip(files).map_err(|e| e.to_string())?;
+        std::fs::write(&opts.output, zip)
+            .map_err(|e| format!("couldn't write {}: {}", opts.output, e))?;
+    } else {
+        let dir = Path::new(&opts.output);
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("couldn't create {}: {}", opts.output, e))?;
+        for file in files {
+            let path = dir.join(&file.name);
+            std::fs::write(&path, &file.bytes)
+                .map_err(|e| format!("couldn't write {}: {}", path.display(), e))?;
+        }
+    }
+    println!("wrote {} images to {}", files.len(), opts.output);
+    Ok(())
+}
+
+async fn run(opts: &Opts) -> Result<(), String> {
+    let decklist = read_decklist(opts.decklist.as_deref())?;
+    let (card_source, image_source) = create_sources(opts)?;
+    let mut card_data = load_card_data(opts, card_source.as_ref()).await?;
+    let cache = match &opts.image_cache_dir {
+        Some(dir) => ScryfallCache::with_store(
+            ImageStore::new(dir).map_err(|e| format!("couldn't open {}: {}", dir, e))?,
+        ),
+        None => ScryfallCache::new(),
+    };
+    let image_cache = SharedImageCache::new(cache);
+
+    let options = ProxyOptions {
+        backside: opts.backside,
+        meld: opts.meld,
+        policy: PrintingPolicy {
+            allow_digital: opts.digital,
+        },
+        tokens: opts.tokens,
+    };
+    let lines =
+        magichawk::proxy_image_lines(&decklist, &mut card_data, options, card_source.as_ref())
+            .await
+            .map_err(|e| e.to_string())?;
+    let image_format = match opts.format {
+        None => {
+            let pages = magichawk::render_pages(&lines, &image_cache, image_source.as_ref())
+                .await
+                .map_err(|e| e.to_string())?;
+            let pdf = magichawk::page_images_to_pdf(pages.into_iter(), opts.paper)
+                .map_err(|e| e.to_string())?;
+            std::fs::write(&opts.output, pdf)
+                .map_err(|e| format!("couldn't write {}: {}", opts.output, e))?;
+            println!("wrote {}", opts.output);
+            return Ok(());
+        }
+        Some(ImageFormat::Png) => ImageFileFormat::Png,
+        Some(ImageFormat::Jpeg) => ImageFileFormat::Jpeg,
+    };
+    let files = if let Some(preset) = opts.print_service {
+        let cards = magichawk::render_cards(&lines, &image_cache, image_source.as_ref())
+            .await
+            .map_err(|e| e.to_string())?;
+        magichawk::print_service_files(&cards, preset, image_format)
+    } else if opts.cards {
+        let cards = magichawk::render_cards(&lines, &image_cache, image_source.as_ref())
+            .await
+            .map_err(|e| e.to_string())?;
+        magichawk::card_files(&cards, image_format, opts.dpi)
+    } else {
+        let pages = magichawk::render_pages(&lines, &image_cache, image_source.as_ref())
+            .await
+            .map_err(|e| e.to_string())?;
+        magichawk::page_files(&pages, image_format, opts.dpi)
+    }
+    .map_err(|e| e.to_string())?;
+    write_images(&files, opts)
+}
+
+fn main() -> ExitCode {
+    let opts = Opts::parse();
+    match rocket::execute(run(&opts)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
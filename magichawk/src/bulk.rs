@@ -0,0 +1,122 @@
+use flate2::read::GzDecoder;
+use serde::de::{Deserializer, SeqAccess, Visitor};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+pub type ScryfallObject = serde_json::Map<String, serde_json::Value>;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Opens a bulk data file of the scryfall API (see https://scryfall.com/docs/api/bulk-data),
+/// gzip compressed files are recognized by their first bytes and decompressed while reading.
+pub fn open_bulk_file<P: AsRef<Path>>(path: P) -> Result<Box<dyn Read>> {
+    let path = path.as_ref();
+    let open_error = |e: std::io::Error| Error::Io(format!("couldn't open {:?}: {}", path, e));
+    let mut reader = BufReader::new(File::open(path).map_err(open_error)?);
+    if reader
+        .fill_buf()
+        .map_err(open_error)?
+        .starts_with(&GZIP_MAGIC)
+    {
+        Ok(Box::new(BufReader::new(GzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Calls `f` with the objects of the json array in the reader one at a time, so that only one of
+/// them is in memory at once however large the bulk data is. Returns the number of objects.
+pub fn for_each_bulk_object<R, F>(reader: R, f: F) -> Result<usize>
+where
+    R: Read,
+    F: FnMut(ScryfallObject),
+{
+    let invalid = |e: serde_json::Error| {
+        if e.is_io() {
+            Error::Io(format!("couldn't read bulk data: {}", e))
+        } else {
+            Error::InvalidFile(format!("invalid bulk data: {}", e))
+        }
+    };
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let count = deserializer
+        .deserialize_seq(BulkVisitor(f))
+        .map_err(invalid)?;
+    deserializer.end().map_err(invalid)?;
+    Ok(count)
+}
+
+/// `for_each_bulk_object` for a bulk data file, compressed or not.
+pub fn read_bulk_file<P, F>(path: P, f: F) -> Result<usize>
+where
+    P: AsRef<Path>,
+    F: FnMut(ScryfallObject),
+{
+    for_each_bulk_object(open_bulk_file(path)?, f)
+}
+
+struct BulkVisitor<F>(F);
+
+impl<'de, F: FnMut(ScryfallObject)> Visitor<'de> for BulkVisitor<F> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "an array of scryfall objects")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> std::result::Result<usize, A::Error> {
+        let mut count = 0;
+        while let Some(object) = seq.next_element::<ScryfallObject>()? {
+            (self.0)(object);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    const MELD: &str = include_str!("../test_input/default-cards_meld.json");
+
+    #[test]
+    fn objects_one_at_a_time() {
+        let mut names = Vec::new();
+        let count = for_each_bulk_object(MELD.as_bytes(), |object| {
+            names.push(object["name"].as_str().unwrap().to_string())
+        })
+        .unwrap();
+        assert_eq!(count, names.len());
+        assert!(names.contains(&"The Mightstone and Weakstone".to_string()));
+        assert!(matches!(
+            for_each_bulk_object(r#"{"name": "not an array"}"#.as_bytes(), |_| {}),
+            Err(Error::InvalidFile(_))
+        ));
+        assert!(for_each_bulk_object("[{}, {}] trailing".as_bytes(), |_| {}).is_err());
+    }
+
+    #[test]
+    fn compressed_and_plain_files() {
+        let dir = std::env::temp_dir().join(format!("magichawk-bulk-gz-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let plain = dir.join("default-cards.json");
+        std::fs::write(&plain, MELD).unwrap();
+        let compressed = dir.join("default-cards.json.gz");
+        let mut encoder = GzEncoder::new(File::create(&compressed).unwrap(), Compression::fast());
+        encoder.write_all(MELD.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let plain_count = read_bulk_file(&plain, |_| {}).unwrap();
+        assert!(plain_count > 0);
+        assert_eq!(read_bulk_file(&compressed, |_| {}).unwrap(), plain_count);
+        assert!(read_bulk_file(dir.join("missing.json"), |_| {}).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::scryfall::CardPrintings;
+
+// binary card data files start with the magic bytes, the format version and the flags
+const MAGIC: &[u8; 4] = b"MHCD";
+const HEADER_LEN: usize = 9;
+const FLAG_COMPRESSED: u8 = 1;
+
+/// The version of the binary card data format. Unlike json the binary format can't leave out
+/// fields or fall back to defaults, so it has to change whenever the printings do.
+pub const CARD_DATA_VERSION: u32 = 1;
+
+/// How a card data file is written, json files can be read by older versions and other tools
+/// while binary files are smaller and load faster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CardDataFormat {
+    #[default]
+    Json,
+    Binary,
+    /// binary and compressed with gzip
+    Compressed,
+}
+
+impl FromStr for CardDataFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(CardDataFormat::Json),
+            "binary" => Ok(CardDataFormat::Binary),
+            "compressed" => Ok(CardDataFormat::Compressed),
+            _ => Err(format!(
+                "unknown card data format {}, expected json, binary or compressed",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CardDataFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CardDataFormat::Json => write!(f, "json"),
+            CardDataFormat::Binary => write!(f, "binary"),
+            CardDataFormat::Compressed => write!(f, "compressed"),
+        }
+    }
+}
+
+fn write_error(e: impl fmt::Display) -> Error {
+    Error::Io(format!("couldn't write card data: {}", e))
+}
+
+fn invalid_card_data(e: impl fmt::Display) -> Error {
+    Error::InvalidFile(format!("invalid card data: {}", e))
+}
+
+impl CardPrintings {
+    pub fn write_to<W: Write>(&self, mut writer: W, format: CardDataFormat) -> Result<()> {
+        let compressed = match format {
+            CardDataFormat::Json => {
+                serde_json::to_writer(writer, self).map_err(write_error)?;
+                return Ok(());
+            }
+            CardDataFormat::Binary => false,
+            CardDataFormat::Compressed => true,
+        };
+        writer.write_all(MAGIC).map_err(write_error)?;
+        writer
+            .write_all(&CARD_DATA_VERSION.to_le_bytes())
+            .map_err(write_error)?;
+        if compressed {
+            writer.write_all(&[FLAG_COMPRESSED]).map_err(write_error)?;
+            let mut encoder = GzEncoder::new(writer, Compression::default());
+            bincode::serialize_into(&mut encoder, self).map_err(write_error)?;
+            encoder.finish().map_err(write_error)?;
+        } else {
+            writer.write_all(&[0]).map_err(write_error)?;
+            bincode::serialize_into(writer, self).map_err(write_error)?;
+        }
+        Ok(())
+    }
+
+    /// Read card data written in any of the formats, binary files of another version are rejected.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CardPrintings> {
+        let Some(header) = bytes.strip_prefix(MAGIC) else {
+            return CardPrintings::from_reader(bytes).map_err(invalid_card_data);
+        };
+        if bytes.len() < HEADER_LEN {
+            return Err(invalid_card_data("binary card data is truncated"));
+        }
+        let version = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        if version != CARD_DATA_VERSION {
+            return Err(invalid_card_data(format!(
+                "binary card data has version {}, only version {} can be read, the card data has \
+                 to be created again",
+                version, CARD_DATA_VERSION
+            )));
+        }
+        let body = &bytes[HEADER_LEN..];
+        if header[4] & FLAG_COMPRESSED != 0 {
+            bincode::deserialize_from(GzDecoder::new(body)).map_err(invalid_card_data)
+        } else {
+            bincode::deserialize(body).map_err(invalid_card_data)
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<CardPrintings> {
+        let path = path.as_ref();
+        let mut bytes = Vec::new();
+        std::fs::File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| Error::Io(format!("couldn't read {:?}: {}", path, e)))?;
+        CardPrintings::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bulk::ScryfallObject;
+    use crate::scryfall::{MinimalScryfallObject, insert_extra_object, is_extra};
+
+    fn printings() -> CardPrintings {
+        let input = include_str!("../test_input/default-cards-20200910090351_truncated.json");
+        let objects: Vec<ScryfallObject> = serde_json::from_str(input).unwrap();
+        let mut printings = CardPrintings::new();
+        for object in objects.iter() {
+            if is_extra(object) {
+                insert_extra_object(&mut printings, object);
+            } else if let Some(minimal) = MinimalScryfallObject::from_dict(object) {
+                printings
+                    .printings
+                    .entry(minimal.name.clone())
+                    .or_default()
+                    .push(minimal);
+            }
+        }
+        printings
+    }
+
+    fn round_trip(format: CardDataFormat) -> Vec<u8> {
+        let original = printings();
+        let mut bytes = Vec::new();
+        original.write_to(&mut bytes, format).unwrap();
+        let read = CardPrintings::from_bytes(&bytes).unwrap();
+        assert_eq!(read.printings, original.printings);
+        assert_eq!(read.extras, original.extras);
+        assert_eq!(read.languages, original.languages);
+        bytes
+    }
+
+    #[test]
+    fn all_formats_round_trip() {
+        let json = round_trip(CardDataFormat::Json);
+        let binary = round_trip(CardDataFormat::Binary);
+        let compressed = round_trip(CardDataFormat::Compressed);
+        assert!(binary.len() < json.len());
+        assert!(compressed.len() < binary.len());
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut bytes = round_trip(CardDataFormat::Binary);
+        bytes[4..8].copy_from_slice(&(CARD_DATA_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            CardPrintings::from_bytes(&bytes),
+            Err(Error::InvalidFile(message)) if message.contains("version")
+        ));
+        assert!(CardPrintings::from_bytes(b"MHCD\x01").is_err());
+        assert!(CardPrintings::from_bytes(&bytes[..20]).is_err());
+    }
+
+    #[test]
+    fn format_names() {
+        for format in [
+            CardDataFormat::Json,
+            CardDataFormat::Binary,
+            CardDataFormat::Compressed,
+        ] {
+            assert_eq!(format.to_string().parse(), Ok(format));
+        }
+        assert!("yaml".parse::<CardDataFormat>().is_err());
+    }
+}
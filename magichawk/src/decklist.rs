@@ -4,12 +4,15 @@ use lazy_static::lazy_static;
 use regex::{Match, Regex};
 use std::collections::HashSet;
 
+/// A line of a decklist, entries in a tokens section are looked up among tokens, emblems and
+/// other helper cards instead of among the cards.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct DecklistEntry {
     pub multiple: i32,
     pub name: String,
     pub set: Option<String>,
     pub lang: Option<String>,
+    pub extra: bool,
 }
 
 impl DecklistEntry {
@@ -19,6 +22,7 @@ impl DecklistEntry {
             name: name.to_string(),
             set: set.map(String::from),
             lang: lang.map(String::from),
+            extra: false,
         }
     }
 
@@ -28,6 +32,7 @@ impl DecklistEntry {
             name: n.to_string(),
             set: None,
             lang: None,
+            extra: false,
         }
     }
 
@@ -37,6 +42,7 @@ impl DecklistEntry {
             name: n.to_string(),
             set: None,
             lang: None,
+            extra: false,
         }
     }
 }
@@ -72,33 +78,52 @@ fn parse_lang(group: Option<Match>, languages: &HashSet<String>) -> Option<Strin
     }
 }
 
-pub fn parse_line(line: &str, languages: &HashSet<String>) -> Option<DecklistEntry> {
+const NON_ENTRIES: [&str; 3] = ["deck", "decklist", "sideboard"];
+const EXTRAS_SECTIONS: [&str; 5] = ["tokens", "token", "emblems", "emblem", "extras"];
+
+// headers are recognized by the parsed name, so an explanation after them is ignored like a set
+enum LineContent {
+    Entry(DecklistEntry),
+    /// whether the following lines are extras
+    SectionHeader(bool),
+    Nothing,
+}
+
+fn parse_line(line: &str, languages: &HashSet<String>) -> LineContent {
     lazy_static! {
         static ref REMNS: Regex =
-            Regex::new(r"^\s*(\d*)\s*([^\(\[\$\t]*)[\s\(\[]*([\dA-Za-z]{2,3})?").unwrap();
+            Regex::new(r"^\s*(\d*)\s*([^\(\[\$\t]*)[\s\(\[]*([\dA-Za-z]{2,4})?").unwrap();
     }
 
-    match REMNS.captures(line) {
-        Some(mns) => {
-            let multiple = parse_multiple(mns.get(1));
-            let name = mns.get(2)?.as_str().trim().to_string();
-            let set_or_lang = mns.get(3);
-            let set = parse_set(set_or_lang);
-            let lang = parse_lang(set_or_lang, languages);
-            let name_lowercase = name.to_lowercase();
-            let non_entries = ["deck", "decklist", "sideboard"];
-            if non_entries.iter().any(|s| **s == name_lowercase) {
-                None
-            } else {
-                Some(DecklistEntry {
-                    multiple,
-                    name,
-                    set,
-                    lang,
-                })
-            }
-        }
-        None => None,
+    let Some(mns) = REMNS.captures(line) else {
+        return LineContent::Nothing;
+    };
+    let Some(name) = mns.get(2) else {
+        return LineContent::Nothing;
+    };
+    let name = name.as_str().trim().to_string();
+    if let Some(extras) = section_header(&name) {
+        return LineContent::SectionHeader(extras);
+    }
+    let set_or_lang = mns.get(3);
+    LineContent::Entry(DecklistEntry {
+        multiple: parse_multiple(mns.get(1)),
+        name,
+        set: parse_set(set_or_lang),
+        lang: parse_lang(set_or_lang, languages),
+        extra: false,
+    })
+}
+
+// a section header like "Tokens" marks all following lines up to the next header as extras
+fn section_header(line: &str) -> Option<bool> {
+    let header = line.trim().trim_end_matches(':').to_lowercase();
+    if EXTRAS_SECTIONS.contains(&header.as_str()) {
+        Some(true)
+    } else if NON_ENTRIES.contains(&header.as_str()) {
+        Some(false)
+    } else {
+        None
     }
 }
 
@@ -106,13 +131,24 @@ pub fn parse_decklist<'a>(
     decklist: &'a str,
     languages: &HashSet<String>,
 ) -> Vec<ParsedDecklistLine<'a>> {
+    let mut in_extras = false;
     decklist
         .lines()
         .map(|s| s.trim())
         .filter(|s| !s.is_empty())
-        .map(|s| ParsedDecklistLine {
-            line: s,
-            entry: parse_line(s, languages),
+        .map(|s| {
+            let entry = match parse_line(s, languages) {
+                LineContent::Entry(entry) => Some(DecklistEntry {
+                    extra: in_extras,
+                    ..entry
+                }),
+                LineContent::SectionHeader(extras) => {
+                    in_extras = extras;
+                    None
+                }
+                LineContent::Nothing => None,
+            };
+            ParsedDecklistLine { line: s, entry }
         })
         .collect()
 }
@@ -123,10 +159,13 @@ mod tests {
 
     fn parse_line_default(s: &str) -> Option<DecklistEntry> {
         let minimal = get_minimal_scryfall_languages();
-        parse_line(s, &minimal)
+        match parse_line(s, &minimal) {
+            LineContent::Entry(entry) => Some(entry),
+            LineContent::SectionHeader(_) | LineContent::Nothing => None,
+        }
     }
 
-    fn parse_decklist_default(s: &str) -> Vec<ParsedDecklistLine> {
+    fn parse_decklist_default(s: &str) -> Vec<ParsedDecklistLine<'_>> {
         let minimal = get_minimal_scryfall_languages();
         parse_decklist(s, &minimal)
     }
@@ -197,7 +236,7 @@ mod tests {
         Instant [1]
         1  Incubation/Incongruity   		--- ";
         let parsed = parse_decklist_default(decklist);
-        let expected = vec![
+        let expected = [
             ParsedDecklistLine {
                 line: "4  Beanstalk Giant   		$0.25",
                 entry: Some(DecklistEntry::from_multiple_name(4, "Beanstalk Giant")),
@@ -237,7 +276,7 @@ mod tests {
         1 Bedeck // Bedazzle (RNA) 221
         1 Spawn of Mayhem (RNA) 85
         ";
-        let expected = vec![
+        let expected = [
             ParsedDecklistLine {
                 line: "Deck",
                 entry: None,
@@ -262,10 +301,88 @@ mod tests {
         }
     }
 
+    #[test]
+    fn tokens_section() {
+        let decklist = "4 Raise the Alarm\n\nTokens:\n8 Soldier (TM20)\nSideboard\n1 Shatter";
+        let expected = [
+            ParsedDecklistLine {
+                line: "4 Raise the Alarm",
+                entry: Some(DecklistEntry::from_multiple_name(4, "Raise the Alarm")),
+            },
+            ParsedDecklistLine {
+                line: "Tokens:",
+                entry: None,
+            },
+            ParsedDecklistLine {
+                line: "8 Soldier (TM20)",
+                entry: Some(DecklistEntry {
+                    extra: true,
+                    ..DecklistEntry::new(8, "Soldier", Some("tm20"), None)
+                }),
+            },
+            ParsedDecklistLine {
+                line: "Sideboard",
+                entry: None,
+            },
+            ParsedDecklistLine {
+                line: "1 Shatter",
+                entry: Some(DecklistEntry::from_multiple_name(1, "Shatter")),
+            },
+        ];
+        let parsed = parse_decklist_default(decklist);
+        assert_eq!(parsed.len(), expected.len());
+        for (left, right) in parsed.iter().zip(expected.iter()) {
+            assert_eq!(left, right);
+        }
+    }
+
+    #[test]
+    fn index_placeholder() {
+        let decklist = "mountain (RNA)
+2 plains [5ed]
+2 expansion // explosion
+kabira plateau (will only print back side)
+ajani, sleeper agent [ph] (will try to use a printing in phyrexian)
+memory lapse [ja] (non-english printings only exist if the art is unique)
+tokens (the following lines are tokens, emblems or dungeons)
+3 soldier (tbro)";
+        let parsed = parse_decklist_default(decklist);
+        assert_eq!(parsed.len(), 8);
+        for line in &parsed[..6] {
+            assert!(!line.entry.as_ref().unwrap().extra, "{}", line.line);
+        }
+        assert_eq!(parsed[6].entry, None);
+        assert_eq!(
+            parsed[7].entry,
+            Some(DecklistEntry {
+                extra: true,
+                ..DecklistEntry::new(3, "soldier", Some("tbro"), None)
+            })
+        );
+    }
+
+    #[test]
+    fn promo_set() {
+        // promo and list sets have four letters like the token sets
+        assert_eq!(
+            parse_line_default("1 Sol Ring (PLST) C21-263"),
+            Some(DecklistEntry::new(1, "Sol Ring", Some("plst"), None))
+        );
+        assert_eq!(
+            parse_line_default("urza, lord protector [pbro]"),
+            Some(DecklistEntry::new(
+                1,
+                "urza, lord protector",
+                Some("pbro"),
+                None
+            ))
+        );
+    }
+
     #[test]
     fn arenaexport2() {
         let decklist = "Deck\n1 Defiant Strike (M21) 15\n24 Plains (ANB) 115\n\nSideboard\n2 Faerie Guidemother (ELD) 11";
-        let expected = vec![
+        let expected = [
             ParsedDecklistLine {
                 line: "Deck",
                 entry: None,
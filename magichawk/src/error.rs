@@ -0,0 +1,167 @@
+use crate::scryfall_client::ClientError;
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::{self, Responder, Response};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Everything that can go wrong on the way from a decklist to a pdf.
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// scryfall couldn't be reached or answered with an unexpected status
+    Network(Arc<ClientError>),
+    /// scryfall still asked us to slow down after retrying
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    /// an answer from scryfall didn't have the expected format
+    Parse(String),
+    /// a local file, e.g. of bulk or card data, couldn't be read or written
+    Io(String),
+    /// a local file didn't have the expected format
+    InvalidFile(String),
+    UnknownCard(String),
+    /// no entry of the decklist could be turned into an image
+    EmptyDecklist,
+    MissingImage(String),
+    /// an option of the request can't be used, e.g. a resolution which is too high
+    InvalidOption(String),
+    Pdf(String),
+    /// the images of an export couldn't be encoded or archived
+    Export(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The status of the http response which reports this error.
+    pub fn status(&self) -> Status {
+        match self {
+            Error::Network(e) if matches!(**e, ClientError::CircuitOpen) => {
+                Status::ServiceUnavailable
+            }
+            Error::Network(_) | Error::Parse(_) | Error::MissingImage(_) => Status::BadGateway,
+            Error::RateLimited { .. } => Status::ServiceUnavailable,
+            Error::UnknownCard(_) => Status::NotFound,
+            Error::EmptyDecklist | Error::InvalidOption(_) => Status::BadRequest,
+            Error::Io(_) | Error::InvalidFile(_) | Error::Pdf(_) | Error::Export(_) => {
+                Status::InternalServerError
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Network(e) => write!(f, "couldn't get data from scryfall: {}", e),
+            Error::RateLimited { .. } => {
+                write!(
+                    f,
+                    "scryfall is receiving too many requests, try again later"
+                )
+            }
+            Error::Parse(e) => write!(f, "couldn't understand data: {}", e),
+            Error::Io(e) => write!(f, "file error: {}", e),
+            Error::InvalidFile(e) => write!(f, "invalid file: {}", e),
+            Error::UnknownCard(name) => write!(f, "unknown card: {}", name),
+            Error::EmptyDecklist => write!(f, "no card names have been recognized"),
+            Error::MissingImage(uri) => write!(f, "couldn't get image {}", uri),
+            Error::InvalidOption(e) => write!(f, "invalid option: {}", e),
+            Error::Pdf(e) => write!(f, "couldn't create pdf: {}", e),
+            Error::Export(e) => write!(f, "couldn't create images: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ClientError> for Error {
+    fn from(e: ClientError) -> Self {
+        match e {
+            ClientError::RateLimited { retry_after } => Error::RateLimited { retry_after },
+            e => Error::Network(Arc::new(e)),
+        }
+    }
+}
+
+// reading the body of a response fails either on the network or because it isn't valid json
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            Error::Parse(e.to_string())
+        } else {
+            Error::from(ClientError::Network(e))
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Error {
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build_from(self.to_string().respond_to(request)?);
+        response.status(self.status()).header(ContentType::Plain);
+        if let Error::RateLimited {
+            retry_after: Some(retry_after),
+        } = self
+        {
+            response.header(Header::new(
+                "Retry-After",
+                retry_after.as_secs().to_string(),
+            ));
+        }
+        response.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_codes() {
+        assert_eq!(
+            Error::from(ClientError::RateLimited { retry_after: None }).status(),
+            Status::ServiceUnavailable
+        );
+        assert_eq!(
+            Error::from(ClientError::CircuitOpen).status(),
+            Status::ServiceUnavailable
+        );
+        assert_eq!(
+            Error::from(ClientError::Status(reqwest::StatusCode::NOT_FOUND)).status(),
+            Status::BadGateway
+        );
+        assert_eq!(
+            Error::UnknownCard("nonsense".to_string()).status(),
+            Status::NotFound
+        );
+        assert_eq!(Error::EmptyDecklist.status(), Status::BadRequest);
+        assert_eq!(
+            Error::InvalidOption("dpi".to_string()).status(),
+            Status::BadRequest
+        );
+        let parse_error = serde_json::from_str::<Vec<String>>("{").unwrap_err();
+        assert_eq!(Error::from(parse_error).status(), Status::BadGateway);
+        assert_eq!(
+            Error::Io("couldn't open \"missing.json\"".to_string()).status(),
+            Status::InternalServerError
+        );
+        assert_eq!(
+            Error::InvalidFile("invalid bulk data".to_string()).status(),
+            Status::InternalServerError
+        );
+    }
+}
@@ -0,0 +1,476 @@
+use printpdf::image_crate::{DynamicImage, ImageFormat, RgbImage, imageops::FilterType};
+use rocket::form::FromFormField;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{Cursor, Write};
+use std::str::FromStr;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::error::{Error, Result};
+use crate::pdf::INCH_DIV_CM;
+use crate::{IMAGE_WIDTH, IMAGE_WIDTH_CM, RenderedCard};
+
+// a page of 3x3 cards at 1200 dpi has about 8700x12300 pixels, over 300 megabytes as rgb before
+// the buffers of the resize and the encoding are added
+const MAX_DPI: u32 = 1200;
+
+/// The resolution of the cards as they are rendered, exports at another resolution are scaled.
+pub fn native_dpi() -> f32 {
+    IMAGE_WIDTH as f32 / IMAGE_WIDTH_CM * INCH_DIV_CM
+}
+
+/// The format of exported images, png is lossless while jpeg is much smaller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, FromFormField)]
+pub enum ImageFileFormat {
+    #[default]
+    Png,
+    Jpeg,
+}
+
+impl ImageFileFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFileFormat::Png => "png",
+            ImageFileFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+impl FromStr for ImageFileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "png" => Ok(ImageFileFormat::Png),
+            "jpeg" | "jpg" => Ok(ImageFileFormat::Jpeg),
+            _ => Err(format!("unknown image format {}, expected png or jpeg", s)),
+        }
+    }
+}
+
+impl fmt::Display for ImageFileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageFileFormat::Png => write!(f, "png"),
+            ImageFileFormat::Jpeg => write!(f, "jpeg"),
+        }
+    }
+}
+
+/// One file of an export, the name is relative to the directory or zip archive it ends up in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportFile {
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+fn scaled_size((width, height): (u32, u32), dpi: u32) -> (u32, u32) {
+    let factor = dpi as f32 / native_dpi();
+    (
+        (width as f32 * factor).round() as u32,
+        (height as f32 * factor).round() as u32,
+    )
+}
+
+/// How many pixels `count` images of the rendered size have once they are exported at the
+/// resolution, to reject exports which would take too much memory before rendering them. `None`
+/// if the number doesn't even fit into a u64.
+pub fn export_pixels(count: u64, size: (u32, u32), dpi: Option<u32>) -> Option<u64> {
+    let (width, height) = match dpi {
+        Some(dpi) => scaled_size(size, dpi),
+        None => size,
+    };
+    count.checked_mul(u64::from(width) * u64::from(height))
+}
+
+fn scaled(image: &DynamicImage, dpi: Option<u32>) -> Result<Cow<'_, DynamicImage>> {
+    let Some(dpi) = dpi else {
+        return Ok(Cow::Borrowed(image));
+    };
+    if dpi == 0 || dpi > MAX_DPI {
+        return Err(Error::InvalidOption(format!(
+            "dpi has to be between 1 and {}",
+            MAX_DPI
+        )));
+    }
+    let (width, height) = scaled_size((image.width(), image.height()), dpi);
+    Ok(Cow::Owned(image.resize_exact(
+        width,
+        height,
+        FilterType::Lanczos3,
+    )))
+}
+
+fn encode(image: &DynamicImage, format: ImageFileFormat, dpi: Option<u32>) -> Result<Vec<u8>> {
+    let image = scaled(image, dpi)?;
+    let mut bytes = Vec::new();
+    let result = match format {
+        ImageFileFormat::Png => image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png),
+        // jpeg has no alpha channel
+        ImageFileFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg),
+    };
+    result.map_err(|e| Error::Export(e.to_string()))?;
+    Ok(bytes)
+}
+
+// the numbers are padded so that the files sort in order
+fn number_width(count: usize) -> usize {
+    count.to_string().len()
+}
+
+fn file_name_part(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+/// One file per page, page-1.png, page-2.png and so on.
+pub fn page_files(
+    pages: &[DynamicImage],
+    format: ImageFileFormat,
+    dpi: Option<u32>,
+) -> Result<Vec<ExportFile>> {
+    let width = number_width(pages.len());
+    pages
+        .iter()
+        .enumerate()
+        .map(|(i, page)| {
+            Ok(ExportFile {
+                name: format!("page-{:0width$}.{}", i + 1, format.extension()),
+                bytes: encode(page, format, dpi)?,
+            })
+        })
+        .collect()
+}
+
+/// One file per distinct card image as online print services want them, the name ends with how
+/// often the card is printed, e.g. 03-lightning-bolt-x4.png.
+pub fn card_files(
+    cards: &[RenderedCard],
+    format: ImageFileFormat,
+    dpi: Option<u32>,
+) -> Result<Vec<ExportFile>> {
+    let printed: Vec<&RenderedCard> = cards.iter().filter(|card| card.multiplicity > 0).collect();
+    let width = number_width(printed.len());
+    printed
+        .iter()
+        .enumerate()
+        .map(|(i, card)| {
+            Ok(ExportFile {
+                name: format!(
+                    "{:0width$}-{}-x{}.{}",
+                    i + 1,
+                    file_name_part(&card.name),
+                    card.multiplicity,
+                    format.extension()
+                ),
+                bytes: encode(&card.image, format, dpi)?,
+            })
+        })
+        .collect()
+}
+
+/// The pixel size of the card images a print service wants, including the bleed around the card
+/// which is cut off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrintServicePreset {
+    pub width: u32,
+    pub height: u32,
+    /// on each side
+    pub bleed: u32,
+}
+
+impl PrintServicePreset {
+    /// MakePlayingCards poker size, 2.5 x 3.5 inches with 0.12 inches of bleed at 300 dpi.
+    pub const MPC: PrintServicePreset = PrintServicePreset {
+        width: 822,
+        height: 1122,
+        bleed: 36,
+    };
+}
+
+impl FromStr for PrintServicePreset {
+    type Err = String;
+
+    /// Either the name of a preset or the size as WIDTHxHEIGHT+BLEED in pixels, e.g. 822x1122+36.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("mpc") {
+            return Ok(PrintServicePreset::MPC);
+        }
+        let invalid = || {
+            format!(
+                "unknown print service {}, expected mpc or a size like 822x1122+36",
+                s
+            )
+        };
+        let (size, bleed) = s.split_once('+').ok_or_else(invalid)?;
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        let preset = PrintServicePreset {
+            width: width.parse().map_err(|_| invalid())?,
+            height: height.parse().map_err(|_| invalid())?,
+            bleed: bleed.parse().map_err(|_| invalid())?,
+        };
+        if preset.width <= 2 * preset.bleed || preset.height <= 2 * preset.bleed {
+            return Err(format!("the bleed of {} leaves no room for the card", s));
+        }
+        Ok(preset)
+    }
+}
+
+impl fmt::Display for PrintServicePreset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}+{}", self.width, self.height, self.bleed)
+    }
+}
+
+// the card fills the size without the bleed, the bleed repeats the outermost pixels of the card
+fn with_bleed(image: &DynamicImage, preset: PrintServicePreset) -> DynamicImage {
+    let (width, height) = (
+        preset.width - 2 * preset.bleed,
+        preset.height - 2 * preset.bleed,
+    );
+    let card = image
+        .resize_exact(width, height, FilterType::Lanczos3)
+        .to_rgb8();
+    let inside = |position: u32, size: u32| position.saturating_sub(preset.bleed).min(size - 1);
+    DynamicImage::ImageRgb8(RgbImage::from_fn(preset.width, preset.height, |x, y| {
+        *card.get_pixel(inside(x, width), inside(y, height))
+    }))
+}
+
+/// A card image of a print service export and how often it is printed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestEntry {
+    pub file: String,
+    pub name: String,
+    pub quantity: i32,
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    width: u32,
+    height: u32,
+    bleed: u32,
+    cards: &'a [ManifestEntry],
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn manifest_csv(entries: &[ManifestEntry]) -> String {
+    let mut csv = "file,name,quantity\n".to_string();
+    for entry in entries {
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            csv_field(&entry.file),
+            csv_field(&entry.name),
+            entry.quantity
+        ));
+    }
+    csv
+}
+
+/// One image per distinct card at the size of the print service, with a manifest of the files and
+/// how often each is printed as manifest.csv and manifest.json.
+pub fn print_service_files(
+    cards: &[RenderedCard],
+    preset: PrintServicePreset,
+    format: ImageFileFormat,
+) -> Result<Vec<ExportFile>> {
+    let printed: Vec<&RenderedCard> = cards.iter().filter(|card| card.multiplicity > 0).collect();
+    let width = number_width(printed.len());
+    let mut files = Vec::new();
+    let mut entries = Vec::new();
+    for (i, card) in printed.iter().enumerate() {
+        let name = format!(
+            "{:0width$}-{}.{}",
+            i + 1,
+            file_name_part(&card.name),
+            format.extension()
+        );
+        files.push(ExportFile {
+            name: name.clone(),
+            bytes: encode(&with_bleed(&card.image, preset), format, None)?,
+        });
+        entries.push(ManifestEntry {
+            file: name,
+            name: card.name.clone(),
+            quantity: card.multiplicity,
+        });
+    }
+    let manifest = Manifest {
+        width: preset.width,
+        height: preset.height,
+        bleed: preset.bleed,
+        cards: &entries,
+    };
+    files.push(ExportFile {
+        name: "manifest.csv".to_string(),
+        bytes: manifest_csv(&entries).into_bytes(),
+    });
+    files.push(ExportFile {
+        name: "manifest.json".to_string(),
+        bytes: serde_json::to_vec_pretty(&manifest).map_err(|e| Error::Export(e.to_string()))?,
+    });
+    Ok(files)
+}
+
+/// A zip archive of the files, stored without compression since the images already are.
+pub fn files_to_zip(files: &[ExportFile]) -> Result<Vec<u8>> {
+    let zip_error = |e: zip::result::ZipError| Error::Export(e.to_string());
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for file in files {
+        zip.start_file(file.name.as_str(), options)
+            .map_err(zip_error)?;
+        zip.write_all(&file.bytes)
+            .map_err(|e| Error::Export(e.to_string()))?;
+    }
+    Ok(zip.finish().map_err(zip_error)?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PAGE_HEIGHT, PAGE_WIDTH};
+    use printpdf::image_crate::{Rgb, RgbImage, load_from_memory};
+    use std::io::Read;
+
+    fn blank(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([255, 255, 255])))
+    }
+
+    #[test]
+    fn image_file_formats() {
+        assert_eq!("PNG".parse(), Ok(ImageFileFormat::Png));
+        assert_eq!("jpg".parse(), Ok(ImageFileFormat::Jpeg));
+        assert!("gif".parse::<ImageFileFormat>().is_err());
+        assert_eq!(ImageFileFormat::Jpeg.extension(), "jpg");
+    }
+
+    #[test]
+    fn pages_at_another_dpi() {
+        let pages = vec![blank(9, 9); 10];
+        let files = page_files(&pages, ImageFileFormat::Png, None).unwrap();
+        assert_eq!(files[0].name, "page-01.png");
+        assert_eq!(files[9].name, "page-10.png");
+        assert_eq!(load_from_memory(&files[0].bytes).unwrap().width(), 9);
+
+        let page = blank(PAGE_WIDTH, PAGE_HEIGHT);
+        let files = page_files(&[page], ImageFileFormat::Jpeg, Some(50)).unwrap();
+        assert_eq!(files[0].name, "page-1.jpg");
+        let scaled = load_from_memory(&files[0].bytes).unwrap();
+        let expected_width = (PAGE_WIDTH as f32 * 50.0 / native_dpi()).round() as u32;
+        assert_eq!(scaled.width(), expected_width);
+
+        assert!(matches!(
+            page_files(&pages, ImageFileFormat::Png, Some(0)),
+            Err(Error::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn pixels_of_an_export() {
+        let page = (PAGE_WIDTH, PAGE_HEIGHT);
+        let native = u64::from(PAGE_WIDTH) * u64::from(PAGE_HEIGHT);
+        assert_eq!(export_pixels(2, page, None), Some(2 * native));
+        // about 27 megapixels at 600 dpi
+        let at_600 = export_pixels(1, page, Some(600)).unwrap();
+        assert!((26_000_000..28_000_000).contains(&at_600));
+        assert_eq!(export_pixels(u64::MAX, (u32::MAX, u32::MAX), None), None);
+    }
+
+    #[test]
+    fn cards_in_a_zip() {
+        let card = |name: &str, multiplicity| RenderedCard {
+            name: name.to_string(),
+            image: blank(9, 9),
+            multiplicity,
+        };
+        let cards = vec![
+            card("Expansion // Explosion", 2),
+            card("Back Only", 0),
+            card("Jötun Grunt", 1),
+        ];
+        let files = card_files(&cards, ImageFileFormat::Png, None).unwrap();
+        let names: Vec<&str> = files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["1-expansion-explosion-x2.png", "2-jötun-grunt-x1.png"]
+        );
+
+        let zipped = files_to_zip(&files).unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(zipped)).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut bytes = Vec::new();
+        archive
+            .by_name("2-jötun-grunt-x1.png")
+            .unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        assert_eq!(bytes, files[1].bytes);
+    }
+
+    #[test]
+    fn print_service_presets() {
+        assert_eq!("MPC".parse(), Ok(PrintServicePreset::MPC));
+        let custom: PrintServicePreset = "750x1050+0".parse().unwrap();
+        assert_eq!((custom.width, custom.height, custom.bleed), (750, 1050, 0));
+        assert_eq!(custom.to_string(), "750x1050+0");
+        assert!("822x1122".parse::<PrintServicePreset>().is_err());
+        assert!("60x80+30".parse::<PrintServicePreset>().is_err());
+    }
+
+    #[test]
+    fn bleed_repeats_the_edges() {
+        // left half red, right half blue
+        let card = DynamicImage::ImageRgb8(RgbImage::from_fn(40, 56, |x, _| {
+            if x < 20 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([0, 0, 255])
+            }
+        }));
+        let preset: PrintServicePreset = "28x36+4".parse().unwrap();
+        let with_bleed = with_bleed(&card, preset).to_rgb8();
+        assert_eq!(with_bleed.dimensions(), (28, 36));
+        assert_eq!(*with_bleed.get_pixel(0, 0), Rgb([255, 0, 0]));
+        assert_eq!(*with_bleed.get_pixel(27, 35), Rgb([0, 0, 255]));
+    }
+
+    #[test]
+    fn print_service_manifest() {
+        let card = |name: &str, multiplicity| RenderedCard {
+            name: name.to_string(),
+            image: blank(9, 9),
+            multiplicity,
+        };
+        let cards = vec![card("Urza, Lord Protector", 2), card("Island", 0)];
+        let preset: PrintServicePreset = "20x30+2".parse().unwrap();
+        let files = print_service_files(&cards, preset, ImageFileFormat::Png).unwrap();
+        let names: Vec<&str> = files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["1-urza-lord-protector.png", "manifest.csv", "manifest.json"]
+        );
+        let image = load_from_memory(&files[0].bytes).unwrap();
+        assert_eq!((image.width(), image.height()), (20, 30));
+        assert_eq!(
+            String::from_utf8(files[1].bytes.clone()).unwrap(),
+            "file,name,quantity\n1-urza-lord-protector.png,\"Urza, Lord Protector\",2\n"
+        );
+        let manifest: serde_json::Value = serde_json::from_slice(&files[2].bytes).unwrap();
+        assert_eq!(manifest["bleed"], 2);
+        assert_eq!(manifest["cards"][0]["quantity"], 2);
+    }
+}
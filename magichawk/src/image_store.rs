@@ -0,0 +1,337 @@
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+
+/// HTTP validators sent along with an image, used for conditional requests once it is stale.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Where the bytes of a stored image come from and when they were last fetched or revalidated.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StoredImageMeta {
+    pub uri: String,
+    pub fetched_at: OffsetDateTime,
+    #[serde(default)]
+    pub validators: Validators,
+}
+
+/// Original image bytes on disk, the file names are derived from the uri so that the web server
+/// and the command line tools can share a directory and find each other's downloads.
+pub struct ImageStore {
+    dir: PathBuf,
+    max_bytes: Option<u64>,
+}
+
+// FNV-1a, unlike the hasher of the standard library it is guaranteed to be stable between runs
+fn uri_hash(uri: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in uri.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+// write to a temporary file first so that readers never see a partially written file
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+impl ImageStore {
+    /// Budget of the image cache of the web server if none is configured, about 15000 images.
+    pub const DEFAULT_MAX_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<ImageStore> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(ImageStore {
+            dir,
+            max_bytes: None,
+        })
+    }
+
+    /// Limits the bytes of stored images when purging, without a limit the store keeps growing
+    /// as long as the images can be revalidated.
+    pub fn with_max_bytes(self, max_bytes: u64) -> ImageStore {
+        ImageStore {
+            max_bytes: Some(max_bytes),
+            ..self
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn paths(&self, uri: &str) -> (PathBuf, PathBuf) {
+        let hash = uri_hash(uri);
+        let subdir = self.dir.join(&hash[..2]);
+        (
+            subdir.join(format!("{}.jpg", hash)),
+            subdir.join(format!("{}.json", hash)),
+        )
+    }
+
+    pub fn meta(&self, uri: &str) -> Option<StoredImageMeta> {
+        let (_, meta_path) = self.paths(uri);
+        let meta: StoredImageMeta = serde_json::from_slice(&fs::read(meta_path).ok()?).ok()?;
+        // guard against hash collisions
+        if meta.uri == uri { Some(meta) } else { None }
+    }
+
+    pub fn contains(&self, uri: &str) -> bool {
+        self.meta(uri).is_some()
+    }
+
+    pub fn load(&self, uri: &str) -> Option<(Vec<u8>, StoredImageMeta)> {
+        let meta = self.meta(uri)?;
+        let (image_path, _) = self.paths(uri);
+        match fs::read(&image_path) {
+            Ok(bytes) => Some((bytes, meta)),
+            Err(e) => {
+                error!("couldn't read stored image {:?}: {}", image_path, e);
+                None
+            }
+        }
+    }
+
+    pub fn store(
+        &self,
+        uri: &str,
+        bytes: &[u8],
+        validators: &Validators,
+    ) -> io::Result<StoredImageMeta> {
+        let meta = StoredImageMeta {
+            uri: uri.to_string(),
+            fetched_at: OffsetDateTime::now_utc(),
+            validators: validators.clone(),
+        };
+        let (image_path, meta_path) = self.paths(uri);
+        if let Some(parent) = image_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomically(&image_path, bytes)?;
+        write_atomically(&meta_path, &serde_json::to_vec(&meta)?)?;
+        Ok(meta)
+    }
+
+    /// Marks the stored image as fresh again after the server confirmed that it is unchanged.
+    pub fn refresh(&self, uri: &str) -> io::Result<StoredImageMeta> {
+        let mut meta = self
+            .meta(uri)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, uri.to_string()))?;
+        meta.fetched_at = OffsetDateTime::now_utc();
+        let (_, meta_path) = self.paths(uri);
+        write_atomically(&meta_path, &serde_json::to_vec(&meta)?)?;
+        Ok(meta)
+    }
+
+    fn image_size(&self, uri: &str) -> u64 {
+        let (image_path, _) = self.paths(uri);
+        fs::metadata(image_path).map_or(0, |metadata| metadata.len())
+    }
+
+    pub fn remove(&self, uri: &str) {
+        let (image_path, meta_path) = self.paths(uri);
+        for path in [meta_path, image_path] {
+            if let Err(e) = fs::remove_file(&path)
+                && e.kind() != io::ErrorKind::NotFound
+            {
+                error!("couldn't remove stored image file {:?}: {}", path, e);
+            }
+        }
+    }
+
+    pub fn entries(&self) -> Vec<StoredImageMeta> {
+        let Ok(subdirs) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        subdirs
+            .filter_map(|subdir| fs::read_dir(subdir.ok()?.path()).ok())
+            .flatten()
+            .filter_map(|file| {
+                let path = file.ok()?.path();
+                if path.extension()? != "json" {
+                    return None;
+                }
+                serde_json::from_slice(&fs::read(path).ok()?).ok()
+            })
+            .collect()
+    }
+
+    /// Removes images older than `max_age`, except for those which can still be revalidated
+    /// if `keep_validated` is set. Then the images fetched or revalidated the longest time ago
+    /// are removed until the rest fits into the byte limit.
+    pub fn purge(&self, max_age: time::Duration, keep_validated: bool) {
+        let n = OffsetDateTime::now_utc();
+        let entries = self.entries();
+        debug!("{} stored images before purging", entries.len());
+        let mut removed = 0;
+        let mut kept = Vec::new();
+        for meta in entries {
+            let revalidatable = keep_validated && !meta.validators.is_empty();
+            if n - meta.fetched_at >= max_age && !revalidatable {
+                self.remove(&meta.uri);
+                removed += 1;
+            } else {
+                kept.push(meta);
+            }
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            let mut sized: Vec<(StoredImageMeta, u64)> = kept
+                .into_iter()
+                .map(|meta| {
+                    let size = self.image_size(&meta.uri);
+                    (meta, size)
+                })
+                .collect();
+            sized.sort_by_key(|(meta, _)| meta.fetched_at);
+            let mut total: u64 = sized.iter().map(|(_, size)| size).sum();
+            for (meta, size) in sized {
+                if total <= max_bytes {
+                    break;
+                }
+                self.remove(&meta.uri);
+                total -= size;
+                removed += 1;
+            }
+        }
+        debug!("{} stored images removed when purging", removed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_store(name: &str) -> ImageStore {
+        let dir = std::env::temp_dir().join(format!(
+            "magichawk-image-store-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        ImageStore::new(dir).unwrap()
+    }
+
+    const URI: &str = "https://cards.scryfall.io/border_crop/front/8/a/8aefe8bd-216a-4ec1-9362-3f9dbf7fd083.jpg?1670539417";
+
+    #[test]
+    fn stable_file_names() {
+        assert_eq!(uri_hash(""), "cbf29ce484222325");
+        assert_eq!(uri_hash("a"), "af63dc4c8601ec8c");
+    }
+
+    #[test]
+    fn roundtrip() {
+        let store = test_store("roundtrip");
+        assert!(store.load(URI).is_none());
+        store
+            .store(URI, b"not really a jpeg", &Validators::default())
+            .unwrap();
+        let (bytes, meta) = store.load(URI).unwrap();
+        assert_eq!(bytes, b"not really a jpeg");
+        assert_eq!(meta.uri, URI);
+        assert_eq!(store.entries().len(), 1);
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+
+    #[test]
+    fn purge() {
+        let store = test_store("purge");
+        store
+            .store(URI, b"not really a jpeg", &Validators::default())
+            .unwrap();
+        store.purge(time::Duration::days(1), false);
+        assert!(store.contains(URI));
+        store.purge(time::Duration::ZERO, true);
+        assert!(!store.contains(URI));
+        assert!(store.entries().is_empty());
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+
+    #[test]
+    fn validated_images_survive_purge() {
+        let store = test_store("validated");
+        let validators = Validators {
+            etag: Some("\"abc\"".to_string()),
+            last_modified: None,
+        };
+        store.store(URI, b"not really a jpeg", &validators).unwrap();
+        store.purge(time::Duration::ZERO, true);
+        assert_eq!(store.meta(URI).unwrap().validators, validators);
+        store.purge(time::Duration::ZERO, false);
+        assert!(!store.contains(URI));
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+
+    // stores an image which was fetched `age` ago
+    fn store_aged(store: &ImageStore, uri: &str, age: time::Duration) {
+        let validators = Validators {
+            etag: Some("\"abc\"".to_string()),
+            last_modified: None,
+        };
+        store.store(uri, &[0; 10], &validators).unwrap();
+        let meta = StoredImageMeta {
+            fetched_at: OffsetDateTime::now_utc() - age,
+            ..store.meta(uri).unwrap()
+        };
+        let (_, meta_path) = store.paths(uri);
+        write_atomically(&meta_path, &serde_json::to_vec(&meta).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn byte_limit() {
+        let store = test_store("byte-limit").with_max_bytes(25);
+        store_aged(&store, "a", time::Duration::days(3));
+        store_aged(&store, "b", time::Duration::days(1));
+        store_aged(&store, "c", time::Duration::days(2));
+        store.purge(time::Duration::days(30), true);
+        assert!(!store.contains("a"));
+        assert!(store.contains("b"));
+        assert!(store.contains("c"));
+        store.purge(time::Duration::ZERO, true);
+        assert_eq!(store.entries().len(), 2);
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+
+    #[test]
+    fn refresh() {
+        let store = test_store("refresh");
+        assert!(store.refresh(URI).is_err());
+        let stored = store
+            .store(URI, b"not really a jpeg", &Validators::default())
+            .unwrap();
+        let refreshed = store.refresh(URI).unwrap();
+        assert!(refreshed.fetched_at >= stored.fetched_at);
+        assert_eq!(store.load(URI).unwrap().0, b"not really a jpeg");
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+
+    #[test]
+    fn meta_without_validators() {
+        let meta = StoredImageMeta {
+            uri: URI.to_string(),
+            fetched_at: OffsetDateTime::now_utc(),
+            validators: Validators::default(),
+        };
+        let mut legacy = serde_json::to_value(&meta).unwrap();
+        legacy.as_object_mut().unwrap().remove("validators");
+        let upgraded: StoredImageMeta = serde_json::from_value(legacy).unwrap();
+        assert_eq!(upgraded, meta);
+    }
+}
@@ -9,36 +9,74 @@ extern crate time;
 extern crate tokio;
 
 use Option::{None, Some};
+use itertools::Itertools;
 use log::{debug, error, info};
 use printpdf::image_crate::{
-    DynamicImage, ImageFormat, Rgb, RgbImage, imageops::overlay, load_from_memory_with_format,
+    DynamicImage, ImageFormat, Rgb, RgbImage,
+    imageops::{FilterType, overlay},
+    load_from_memory_with_format,
 };
 use rocket::form::FromFormField;
-use std::collections::hash_map::Entry::{Occupied, Vacant};
+use rocket::futures::StreamExt;
+use std::borrow::Cow;
 use std::fmt;
+use std::str::FromStr;
 use std::string::String;
+use std::sync::Arc;
 use time::OffsetDateTime;
 
+mod bulk;
+pub use crate::bulk::{ScryfallObject, for_each_bulk_object, open_bulk_file, read_bulk_file};
+
+mod card_data_file;
+pub use crate::card_data_file::{CARD_DATA_VERSION, CardDataFormat};
+
 mod decklist;
 pub use crate::decklist::parse_decklist;
 use crate::decklist::{DecklistEntry, ParsedDecklistLine};
 
+mod error;
+pub use crate::error::{Error, Result};
+
+mod export;
+pub use crate::export::{
+    ExportFile, ImageFileFormat, ManifestEntry, PrintServicePreset, card_files, export_pixels,
+    files_to_zip, page_files, print_service_files,
+};
+
+mod image_store;
+pub use crate::image_store::{ImageStore, StoredImageMeta, Validators};
+
 mod lookup;
 use crate::lookup::{CardNameLookup, NameLookupResult, NameMatchMode};
 
+mod merge;
+pub use crate::merge::{CardDataMerge, MergeReport};
+
 mod pdf;
-pub use crate::pdf::page_images_to_pdf;
+pub use crate::pdf::{PaperSize, page_images_to_pdf};
+
+mod rate_limit;
+pub use crate::rate_limit::RateLimiter;
 
 mod scryfall;
+use scryfall::get_minimal_scryfall_languages;
 pub use scryfall::{
-    CardPrintings, MinimalScryfallObject, ScryfallCardNames, get_minimal_card_printings,
-    insert_scryfall_object,
+    CardFace, CardPrintings, MinimalScryfallObject, RelatedCard, ScryfallCardNames,
+    insert_extra_object, insert_scryfall_object, is_card, is_extra, query_scryfall_released_since,
 };
-use scryfall::{get_minimal_scryfall_languages, query_scryfall_by_name};
+
+mod source;
+pub use crate::source::{BulkDataSource, CardSource, FixtureSource, ImageSource};
+
+mod validate;
+pub use crate::validate::{CardDataStats, Problem};
 
 mod scryfall_client;
-pub use crate::scryfall_client::ScryfallClient;
-pub use crate::scryfall_client::blocking_call;
+pub use crate::scryfall_client::{ClientError, RetryPolicy, ScryfallClient, ScryfallEndpoints};
+pub use crate::scryfall_client::{
+    SCRYFALL_API_BASE, SCRYFALL_COOLDOWN, SCRYFALL_IMAGE_BASE, blocking_call, scryfall_rate_limiter,
+};
 
 pub const IMAGE_WIDTH: u32 = 480;
 pub const IMAGE_HEIGHT: u32 = 680;
@@ -49,34 +87,86 @@ pub const PAGE_HEIGHT: u32 = 3 * IMAGE_HEIGHT;
 pub const IMAGE_HEIGHT_CM: f32 = 8.7;
 pub const IMAGE_WIDTH_CM: f32 = IMAGE_HEIGHT_CM * IMAGE_WIDTH as f32 / IMAGE_HEIGHT as f32;
 
+/// The part of an image which is printed, the backs of meld parts each show half of the meld
+/// result rotated by 90 degrees.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum ImageSection {
+    Full,
+    TopHalf,
+    BottomHalf,
+}
+
+impl ImageSection {
+    pub fn extract<'a>(&self, image: &'a DynamicImage) -> Cow<'a, DynamicImage> {
+        let (width, height) = (image.width(), image.height());
+        let half = match self {
+            ImageSection::Full => return Cow::Borrowed(image),
+            ImageSection::TopHalf => image.crop_imm(0, 0, width, height / 2),
+            ImageSection::BottomHalf => image.crop_imm(0, height / 2, width, height - height / 2),
+        };
+        Cow::Owned(
+            half.rotate90()
+                .resize_exact(IMAGE_WIDTH, IMAGE_HEIGHT, FilterType::Lanczos3),
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct CardImage {
+    pub name: String,
+    pub uri: String,
+    pub section: ImageSection,
+}
+
+impl CardImage {
+    pub fn from_face(face: &CardFace) -> CardImage {
+        CardImage {
+            name: face.name.clone(),
+            uri: face.border_crop.clone(),
+            section: ImageSection::Full,
+        }
+    }
+}
+
+/// The images for one decklist entry, images in shared_images (the whole meld result) are
+/// printed once for all entries they appear in instead of once per entry.
 pub struct ImageLine {
     pub name: String,
-    pub images: Vec<(String, i32)>,
+    pub images: Vec<(CardImage, i32)>,
+    pub shared_images: Vec<(CardImage, i32)>,
+    pub tokens: Vec<RelatedCard>,
 }
 
 pub struct CardData {
     pub card_names: ScryfallCardNames,
     pub lookup: CardNameLookup,
+    pub extras_lookup: CardNameLookup,
     pub printings: CardPrintings,
 }
 
 impl CardData {
-    pub async fn from_client(client: &ScryfallClient) -> Option<CardData> {
-        let card_names = ScryfallCardNames::from_api_call(client).await?;
+    pub async fn from_source(source: &dyn CardSource) -> Result<CardData> {
+        let card_names = source.card_names().await?;
 
         let lookup = CardNameLookup::from_card_names(&card_names.names);
-        Some(CardData {
+        Ok(CardData {
             card_names,
             lookup,
+            extras_lookup: CardNameLookup::from_card_names(&[]),
             printings: CardPrintings::new(),
         })
     }
 
     pub async fn from_printings(
         printings: CardPrintings,
-        client: &ScryfallClient,
-    ) -> Option<CardData> {
-        let card_names = ScryfallCardNames::from_api_call(client).await?;
+        source: &dyn CardSource,
+    ) -> Result<CardData> {
+        let card_names = source.card_names().await?;
+        Ok(CardData::with_card_names(printings, card_names))
+    }
+
+    /// Card data without asking a card source for the card names, e.g. when it can't be reached.
+    pub fn with_card_names(printings: CardPrintings, card_names: ScryfallCardNames) -> CardData {
         let lookup = CardNameLookup::from_card_names(&card_names.names);
         let printings_lowercase = printings
             .printings
@@ -85,142 +175,382 @@ impl CardData {
             .collect();
         let mut languages = get_minimal_scryfall_languages();
         languages.extend(printings.languages);
-        Some(CardData {
+        let extras_names: Vec<String> = printings.extras.keys().cloned().collect();
+        CardData {
             card_names,
             lookup,
+            extras_lookup: CardNameLookup::from_card_names(&extras_names),
             printings: CardPrintings {
                 printings: printings_lowercase,
                 languages,
+                extras: printings.extras,
             },
-        })
+        }
     }
 
-    pub async fn update_names(&mut self, client: &ScryfallClient) -> Option<()> {
-        self.card_names = ScryfallCardNames::from_api_call(client).await?;
+    pub async fn update_names(&mut self, source: &dyn CardSource) -> Result<()> {
+        self.card_names = source.card_names().await?;
         self.lookup = CardNameLookup::from_card_names(&self.card_names.names);
-        Some(())
+        Ok(())
+    }
+
+    async fn ensure_contains(
+        &mut self,
+        lookup: &NameLookupResult,
+        source: &dyn CardSource,
+    ) -> Result<()> {
+        if self.printings.printings.contains_key(&lookup.name) {
+            debug!("there is card data for name {}", lookup.name);
+            return Ok(());
+        }
+        let objects = source
+            .printings_by_name(&lookup.name)
+            .await
+            .inspect_err(|e| error!("querying scryfall for name {} failed: {}", lookup.name, e))?;
+        for object in objects.iter() {
+            insert_scryfall_object(&mut self.printings, &self.card_names, object);
+        }
+        Ok(())
     }
 
-    async fn ensure_contains(&mut self, lookup: &NameLookupResult, client: &ScryfallClient) {
-        let entry = self.printings.printings.entry(lookup.name.clone());
-        match entry {
-            Occupied(_) => {
-                debug!("there is card data for name {}", entry.key());
+    async fn ensure_contains_extra(&mut self, name: &str, source: &dyn CardSource) -> Result<()> {
+        if self.printings.extras.contains_key(name) {
+            debug!("there is extra data for name {}", name);
+            return Ok(());
+        }
+        let objects = source
+            .extras_by_name(name)
+            .await
+            .inspect_err(|e| error!("querying scryfall for extra {} failed: {}", name, e))?;
+        for object in objects.iter() {
+            self.insert_extra(object);
+        }
+        Ok(())
+    }
+
+    fn insert_extra(&mut self, object: &serde_json::Map<String, serde_json::Value>) {
+        insert_extra_object(&mut self.printings, object);
+        if let Some(name) = object.get("name").and_then(|name| name.as_str()) {
+            self.extras_lookup.insert(name);
+        }
+    }
+
+    /// Tokens, emblems and other helper cards are looked up by their exact name first since
+    /// there is no catalog of their names to match against.
+    pub async fn get_extra(
+        &mut self,
+        entry: &DecklistEntry,
+        default_mode: BacksideMode,
+        policy: PrintingPolicy,
+        source: &dyn CardSource,
+    ) -> Result<ImageLine> {
+        let unknown = || Error::UnknownCard(entry.name.clone());
+        let exact_name = entry.name.to_lowercase();
+        self.ensure_contains_extra(&exact_name, source).await?;
+        let name = if self.printings.extras.contains_key(&exact_name) {
+            exact_name
+        } else {
+            self.extras_lookup
+                .find(&exact_name)
+                .ok_or_else(unknown)?
+                .name
+        };
+        debug!("extra name in get_extra: {}", name);
+        let matchingprintings = self.printings.extras.get(&name).ok_or_else(unknown)?;
+        // token sets have the code of their card set prefixed with a t
+        let set = entry.set.as_ref().map(|set| {
+            let set = set.to_lowercase();
+            if matchingprintings.iter().any(|p| p.set == set) {
+                set
+            } else {
+                format!("t{}", set)
             }
-            Vacant(token) => {
-                let scryfall_objects = query_scryfall_by_name(token.key(), client).await;
-                match scryfall_objects {
-                    Some(ref objects) => {
-                        for object in objects.iter() {
-                            insert_scryfall_object(&mut self.printings, &self.card_names, object);
-                        }
-                    }
-                    None => {
-                        error!("querying scryfall for name {} failed", token.key());
-                    }
+        });
+        let extra_entry = DecklistEntry {
+            set,
+            ..entry.clone()
+        };
+        let printing =
+            select_printing(matchingprintings, &extra_entry, policy).ok_or_else(unknown)?;
+        let multiplicities =
+            face_multiplicities(printing.faces.len(), default_mode, None, entry.multiple);
+        let images = printing
+            .faces
+            .iter()
+            .map(CardImage::from_face)
+            .zip(multiplicities)
+            .filter(|(_, mult)| *mult > 0)
+            .collect();
+        Ok(ImageLine {
+            name: entry.name.clone(),
+            images,
+            shared_images: Vec::new(),
+            tokens: Vec::new(),
+        })
+    }
+
+    /// One of each distinct token, emblem or dungeon created by the cards in the image lines.
+    pub async fn get_related_tokens(
+        &mut self,
+        image_lines: &[ImageLine],
+        policy: PrintingPolicy,
+        source: &dyn CardSource,
+    ) -> Result<Vec<ImageLine>> {
+        let mut related: Vec<&RelatedCard> = Vec::new();
+        for token in image_lines.iter().flat_map(|line| &line.tokens) {
+            if !related.contains(&token) {
+                related.push(token);
+            }
+        }
+        let mut token_lines = Vec::new();
+        for token in related {
+            if self.printings.find_extra_by_id(&token.id).is_none() {
+                match source.card_by_id(&token.id).await {
+                    Ok(object) => self.insert_extra(&object),
+                    Err(Error::UnknownCard(_)) => info!("scryfall doesn't know token {:?}", token),
+                    Err(e) => return Err(e),
                 }
             }
+            match self.printings.find_extra_by_id(&token.id) {
+                Some(extra) if policy.allow_digital || !extra.digital => {
+                    token_lines.push(ImageLine {
+                        name: extra.name.clone(),
+                        images: extra
+                            .faces
+                            .iter()
+                            .map(|face| (CardImage::from_face(face), 1))
+                            .collect(),
+                        shared_images: Vec::new(),
+                        tokens: Vec::new(),
+                    });
+                }
+                _ => debug!("no printable extra for token {:?}", token),
+            }
         }
+        Ok(token_lines)
     }
 
     pub async fn get_card(
         &mut self,
         entry: &DecklistEntry,
         default_mode: BacksideMode,
-        client: &ScryfallClient,
-    ) -> Option<ImageLine> {
-        let namelookup = self.lookup.find(&entry.name)?;
+        meld_mode: MeldMode,
+        policy: PrintingPolicy,
+        source: &dyn CardSource,
+    ) -> Result<ImageLine> {
+        let unknown = || Error::UnknownCard(entry.name.clone());
+        let namelookup = self.lookup.find(&entry.name).ok_or_else(unknown)?;
         debug!("namelookup in get_card: {:?}", namelookup);
-        let backside = match namelookup.hit {
-            NameMatchMode::Part(1) => BacksideMode::BackOnly,
-            _ => default_mode,
-        };
-        debug!("backside in get_card: {:?}", backside);
-        self.ensure_contains(&namelookup, client).await;
-        let matchingprintings = self.printings.printings.get(&namelookup.name)?;
-        let set_matches = |p: &&MinimalScryfallObject| match &entry.set {
-            Some(s) => p.set == s.to_lowercase(),
-            None => false,
-        };
-        let lang_matches = |p: &&MinimalScryfallObject| match &entry.lang {
-            Some(lang) => p.language == lang.to_lowercase(),
-            None => false,
-        };
-        let printing_right_set = matchingprintings.iter().find(set_matches);
-        let printing_right_lang = matchingprintings.iter().find(lang_matches);
-        let mut printing = if printing_right_set.is_some() {
-            printing_right_set?.clone()
-        } else if printing_right_lang.is_some() {
-            printing_right_lang?.clone()
-        } else {
-            let lang_en = |p: &&MinimalScryfallObject| p.language.to_lowercase() == "en";
-            let printing_en = matchingprintings.iter().find(lang_en);
-            printing_en
-                .unwrap_or(matchingprintings.iter().next()?)
-                .clone()
+        // if a face other than the front is named only that face will be printed
+        let named_face = match namelookup.hit {
+            NameMatchMode::Part(i) if i > 0 => Some(i),
+            _ => None,
         };
-        if let Some(meld_result) = &printing.meld_result {
-            let meld_result_lookup = self.lookup.find(meld_result);
-            if let Some(meld_result_lookup) = meld_result_lookup {
-                self.ensure_contains(&meld_result_lookup, client).await;
-                let matchingprintings_meld =
-                    self.printings.printings.get(&meld_result_lookup.name)?;
-                let printing_meld = matchingprintings_meld
-                    .iter()
-                    .find(set_matches)
-                    .unwrap_or(matchingprintings_meld.iter().next()?);
-                printing.border_crop_back = Some(printing_meld.border_crop.clone());
-            }
-        }
-        let frontmult = if backside == BacksideMode::BackOnly && printing.border_crop_back.is_some()
+        self.ensure_contains(&namelookup, source).await?;
+        let matchingprintings = self
+            .printings
+            .printings
+            .get(&namelookup.name)
+            .ok_or_else(unknown)?;
+        let printing = select_printing(matchingprintings, entry, policy)
+            .ok_or_else(unknown)?
+            .clone();
+        let mut sides: Vec<CardImage> = printing.faces.iter().map(CardImage::from_face).collect();
+        let mut meld_back = None;
+        if let Some(meld_result) = &printing.meld_result
+            && let Some(meld_result_lookup) = self.lookup.find(meld_result)
         {
-            0
-        } else {
-            entry.multiple
-        };
-        let backmult = if printing.border_crop_back.is_some() {
-            match backside {
-                BacksideMode::Zero => 0,
-                BacksideMode::One => 1,
-                BacksideMode::Matching | BacksideMode::BackOnly => entry.multiple,
-            }
-        } else {
-            0
-        };
-        debug!("frontmult: {}, backmult: {}", frontmult, backmult);
-        let mut images = Vec::new();
-        if frontmult > 0 {
-            images.push((printing.border_crop, frontmult))
+            self.ensure_contains(&meld_result_lookup, source).await?;
+            meld_back = self
+                .printings
+                .printings
+                .get(&meld_result_lookup.name)
+                .and_then(|results| meld_back_image(&printing, results, meld_mode, policy));
+            debug!("meld back in get_card: {:?}", meld_back);
+        }
+        if let Some(back) = &meld_back {
+            sides.push(back.clone());
         }
-        if backmult > 0 {
-            images.push((printing.border_crop_back.unwrap(), backmult))
+        let multiplicities =
+            face_multiplicities(sides.len(), default_mode, named_face, entry.multiple);
+        debug!("face multiplicities: {:?}", multiplicities);
+        let mut images: Vec<(CardImage, i32)> = sides.into_iter().zip(multiplicities).collect();
+        let mut shared_images = Vec::new();
+        if meld_back.is_some() && meld_mode == MeldMode::Whole {
+            shared_images.extend(images.pop());
         }
-        Some(ImageLine {
+        images.retain(|(_, mult)| *mult > 0);
+        shared_images.retain(|(_, mult)| *mult > 0);
+        Ok(ImageLine {
             name: entry.name.clone(),
             images,
+            shared_images,
+            tokens: printing.tokens,
         })
     }
 }
 
+// the meld part sharing its collector number with the meld result (238a and 238b) carries the
+// top half of the meld result on its back, promotional variants are marked by a suffix (238as)
+fn collector_number_base(collector_number: &str) -> &str {
+    collector_number.trim_end_matches(|c: char| !c.is_ascii_digit())
+}
+
+fn collector_number_variant(collector_number: &str) -> &str {
+    let suffix = collector_number.trim_start_matches(|c: char| c.is_ascii_digit());
+    suffix.strip_prefix(['a', 'b']).unwrap_or(suffix)
+}
+
+fn meld_back_image(
+    part: &MinimalScryfallObject,
+    results: &[MinimalScryfallObject],
+    meld_mode: MeldMode,
+    policy: PrintingPolicy,
+) -> Option<CardImage> {
+    let part_number = part.collector_number.as_deref().unwrap_or_default();
+    let same_variant = results.iter().find(|result| {
+        (policy.allow_digital || !result.digital)
+            && result.set == part.set
+            && result.language == part.language
+            && result
+                .collector_number
+                .as_deref()
+                .map(collector_number_variant)
+                == Some(collector_number_variant(part_number))
+    });
+    let same_set = DecklistEntry::new(
+        1,
+        part.meld_result.as_deref().unwrap_or_default(),
+        Some(part.set.as_str()),
+        Some(part.language.as_str()),
+    );
+    let result = same_variant.or_else(|| select_printing(results, &same_set, policy))?;
+    let section = match meld_mode {
+        MeldMode::Whole => ImageSection::Full,
+        MeldMode::Halves => {
+            let result_number = result.collector_number.as_deref().unwrap_or_default();
+            if collector_number_base(result_number) == collector_number_base(part_number) {
+                ImageSection::TopHalf
+            } else {
+                ImageSection::BottomHalf
+            }
+        }
+    };
+    Some(CardImage {
+        section,
+        ..CardImage::from_face(result.front()?)
+    })
+}
+
+fn face_multiplicities(
+    n_faces: usize,
+    backside: BacksideMode,
+    named_face: Option<usize>,
+    multiple: i32,
+) -> Vec<i32> {
+    if let Some(named) = named_face
+        && named < n_faces
+    {
+        return (0..n_faces)
+            .map(|i| if i == named { multiple } else { 0 })
+            .collect();
+    }
+    let frontmult = if backside == BacksideMode::BackOnly && n_faces > 1 {
+        0
+    } else {
+        multiple
+    };
+    let backmult = match backside {
+        BacksideMode::Zero => 0,
+        BacksideMode::One => 1,
+        BacksideMode::Matching | BacksideMode::BackOnly => multiple,
+    };
+    std::iter::once(frontmult)
+        .chain(std::iter::repeat_n(backmult, n_faces.saturating_sub(1)))
+        .collect()
+}
+
+fn select_printing<'a>(
+    printings: &'a [MinimalScryfallObject],
+    entry: &DecklistEntry,
+    policy: PrintingPolicy,
+) -> Option<&'a MinimalScryfallObject> {
+    let eligible: Vec<&MinimalScryfallObject> = printings
+        .iter()
+        .filter(|p| policy.allow_digital || !p.digital)
+        .collect();
+    let set_matches = |p: &&MinimalScryfallObject| match &entry.set {
+        Some(s) => p.set == s.to_lowercase(),
+        None => false,
+    };
+    let lang_matches = |p: &&MinimalScryfallObject| match &entry.lang {
+        Some(lang) => p.language == lang.to_lowercase(),
+        None => false,
+    };
+    let lang_en = |p: &&MinimalScryfallObject| p.language.to_lowercase() == "en";
+    let printing_right_set = eligible.iter().copied().find(set_matches);
+    let printing_right_lang = eligible.iter().copied().find(lang_matches);
+    let printing_en = eligible.iter().copied().find(lang_en);
+    printing_right_set
+        .or(printing_right_lang)
+        .or(printing_en)
+        .or(eligible.first().copied())
+}
+
 pub async fn image_lines_from_decklist(
     parsed: Vec<ParsedDecklistLine<'_>>,
     card_data: &mut CardData,
     default_backside_mode: BacksideMode,
-    client: &ScryfallClient,
-) -> Vec<ImageLine> {
+    meld_mode: MeldMode,
+    policy: PrintingPolicy,
+    source: &dyn CardSource,
+) -> Result<Vec<ImageLine>> {
     let mut image_lines = Vec::<ImageLine>::new();
     for line in parsed {
         let entry = &line.as_entry();
-        if let Some(entry) = entry {
-            if let Some(image_line) = card_data
-                .get_card(entry, default_backside_mode, client)
-                .await
-            {
-                image_lines.push(image_line);
+        let image_line = match entry {
+            Some(entry) if entry.extra => {
+                card_data
+                    .get_extra(entry, default_backside_mode, policy, source)
+                    .await
+            }
+            Some(entry) => {
+                card_data
+                    .get_card(entry, default_backside_mode, meld_mode, policy, source)
+                    .await
             }
+            None => continue,
+        };
+        // a line which isn't recognized shouldn't spoil the rest of the decklist
+        match image_line {
+            Ok(image_line) => image_lines.push(image_line),
+            Err(Error::UnknownCard(name)) => info!("skipping unknown card {}", name),
+            Err(e) => return Err(e),
+        }
+    }
+    let shared_lines = merge_shared_images(&image_lines);
+    image_lines.extend(shared_lines);
+    Ok(image_lines)
+}
+
+// a shared image is printed as often as the entry which needs it most often requires
+fn merge_shared_images(image_lines: &[ImageLine]) -> Vec<ImageLine> {
+    let mut merged: Vec<(CardImage, i32)> = Vec::new();
+    for (image, mult) in image_lines.iter().flat_map(|line| &line.shared_images) {
+        match merged.iter_mut().find(|(known, _)| known == image) {
+            Some((_, known_mult)) => *known_mult = (*known_mult).max(*mult),
+            None => merged.push((image.clone(), *mult)),
         }
     }
-    image_lines
+    merged
+        .into_iter()
+        .map(|(image, mult)| ImageLine {
+            name: image.name.clone(),
+            images: vec![(image, mult)],
+            shared_images: Vec::new(),
+            tokens: Vec::new(),
+        })
+        .collect()
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone, FromFormField)]
@@ -231,48 +561,175 @@ pub enum BacksideMode {
     BackOnly,
 }
 
-pub async fn query_image_uri(uri: &str, client: &ScryfallClient) -> Option<DynamicImage> {
-    debug!("scryfall uri: {}", uri);
+/// How the backs of meld cards are printed, either the whole meld result once for all parts of
+/// the meld or on each part the half of the meld result which is on the back of the real card.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, FromFormField)]
+pub enum MeldMode {
+    Whole,
+    Halves,
+}
+
+impl FromStr for BacksideMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "zero" => Ok(BacksideMode::Zero),
+            "one" => Ok(BacksideMode::One),
+            "matching" => Ok(BacksideMode::Matching),
+            "backonly" | "back-only" => Ok(BacksideMode::BackOnly),
+            _ => Err(format!(
+                "unknown backside mode {}, expected zero, one, matching or back-only",
+                s
+            )),
+        }
+    }
+}
+
+impl FromStr for MeldMode {
+    type Err = String;
 
-    let request = client.call(uri).await;
-    match request {
-        Ok(response) => match response.bytes().await {
-            Ok(b) => match load_from_memory_with_format(&b, ImageFormat::Jpeg) {
-                Ok(im) => Some(im),
-                Err(e) => {
-                    error!("error converting response to jpeg: {}", e);
-                    None
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "whole" => Ok(MeldMode::Whole),
+            "halves" => Ok(MeldMode::Halves),
+            _ => Err(format!("unknown meld mode {}, expected whole or halves", s)),
+        }
+    }
+}
+
+/// Restrictions on which printings of a card are used, digital-only printings (Arena, Alchemy,
+/// MTGO) are skipped unless explicitly allowed since their images aren't meant for paper.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub struct PrintingPolicy {
+    pub allow_digital: bool,
+}
+
+/// Everything about a decklist which decides which images are printed.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct ProxyOptions {
+    pub backside: BacksideMode,
+    pub meld: MeldMode,
+    pub policy: PrintingPolicy,
+    /// also print the tokens, emblems and other helper cards the cards create
+    pub tokens: bool,
+}
+
+/// Result of an image request which may be conditional on the validators of a cached copy.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ImageResponse {
+    NotModified,
+    Modified {
+        bytes: Vec<u8>,
+        validators: Validators,
+    },
+}
+
+fn conditional_headers(validators: &Validators) -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    let conditions = [
+        (reqwest::header::IF_NONE_MATCH, &validators.etag),
+        (
+            reqwest::header::IF_MODIFIED_SINCE,
+            &validators.last_modified,
+        ),
+    ];
+    for (name, value) in conditions {
+        if let Some(value) = value
+            && let Ok(value) = reqwest::header::HeaderValue::from_str(value)
+        {
+            headers.insert(name, value);
+        }
+    }
+    headers
+}
+
+fn validators_from_headers(headers: &reqwest::header::HeaderMap) -> Validators {
+    let get = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    Validators {
+        etag: get(reqwest::header::ETAG),
+        last_modified: get(reqwest::header::LAST_MODIFIED),
+    }
+}
+
+pub async fn query_image_conditional(
+    uri: &str,
+    validators: &Validators,
+    client: &ScryfallClient,
+) -> Result<ImageResponse> {
+    let download_uri = client.endpoints().image(uri);
+    debug!("scryfall uri: {}", download_uri);
+
+    let response = client
+        .call_with_headers(&download_uri, conditional_headers(validators))
+        .await
+        .map_err(|e| {
+            info!("error in image request {}: {}", download_uri, e);
+            match e {
+                // anything else, e.g. an outage which outlasted the retries, is reported as such
+                ClientError::Status(reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE) => {
+                    Error::MissingImage(uri.to_string())
                 }
-            },
-            Err(e) => {
-                info!("error in getting bytes of image: {}", e);
-                None
+                e => e.into(),
             }
-        },
-        Err(e) => {
-            info!("error in image request: {}", e);
-            None
-        }
+        })?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(ImageResponse::NotModified);
     }
+    let validators = validators_from_headers(response.headers());
+    let bytes = response.bytes().await?;
+    Ok(ImageResponse::Modified {
+        bytes: bytes.to_vec(),
+        validators,
+    })
+}
+
+pub async fn query_image_bytes(uri: &str, client: &ScryfallClient) -> Result<Vec<u8>> {
+    match query_image_conditional(uri, &Validators::default(), client).await? {
+        ImageResponse::Modified { bytes, .. } => Ok(bytes),
+        ImageResponse::NotModified => Err(Error::MissingImage(uri.to_string())),
+    }
+}
+
+fn decode_image(bytes: &[u8]) -> Result<DynamicImage> {
+    load_from_memory_with_format(bytes, ImageFormat::Jpeg)
+        .map_err(|e| Error::Parse(format!("error converting response to jpeg: {}", e)))
+}
+
+pub async fn query_image_uri(uri: &str, client: &ScryfallClient) -> Result<DynamicImage> {
+    decode_image(&query_image_bytes(uri, client).await?)
 }
 
 pub struct CachedImageResponse {
     t: OffsetDateTime,
-    image: DynamicImage,
+    bytes: Vec<u8>,
+    validators: Validators,
+    last_used: u64,
 }
 
 impl CachedImageResponse {
-    pub fn from_image(i: DynamicImage) -> CachedImageResponse {
+    pub fn from_bytes(bytes: Vec<u8>) -> CachedImageResponse {
         CachedImageResponse {
             t: OffsetDateTime::now_utc(),
-            image: i,
+            bytes,
+            validators: Validators::default(),
+            last_used: 0,
         }
     }
+
+    fn is_fresh(&self) -> bool {
+        OffsetDateTime::now_utc() - self.t < ScryfallCache::get_max_age()
+    }
 }
 
 impl fmt::Display for CachedImageResponse {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "created at {}", self.t)
+        write!(f, "created at {}, {} bytes", self.t, self.bytes.len())
     }
 }
 
@@ -282,12 +739,130 @@ impl fmt::Debug for CachedImageResponse {
     }
 }
 
+/// Where an image was found without going to the network.
+#[derive(Debug, PartialEq, Eq)]
+enum CacheLookup {
+    Fresh,
+    /// present but too old, needs to be revalidated with these validators
+    Stale(Validators),
+    Missing,
+}
+
+/// Upper bounds for the images kept in memory, `None` means unbounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheBudget {
+    pub max_bytes: Option<usize>,
+    pub max_entries: Option<usize>,
+}
+
+impl Default for CacheBudget {
+    fn default() -> Self {
+        CacheBudget {
+            max_bytes: Some(256 * 1024 * 1024),
+            max_entries: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheMetrics {
+    pub hits: u64,
+    pub store_hits: u64,
+    pub misses: u64,
+    pub revalidations: u64,
+    pub not_modified: u64,
+    pub evictions: u64,
+    /// requests which waited for a download started by another request
+    pub deduplicated: u64,
+}
+
+/// Compressed images in memory, evicted least recently used first once the budget is exceeded.
+/// Backed by an optional image store on disk which keeps the original bytes across restarts.
+/// Images older than the maximum age are revalidated with a conditional request before use.
 pub struct ScryfallCache {
     last_purge: OffsetDateTime,
     images: std::collections::HashMap<String, CachedImageResponse>,
+    store: Option<Arc<ImageStore>>,
+    budget: CacheBudget,
+    metrics: CacheMetrics,
+    total_bytes: usize,
+    tick: u64,
+    recency: std::collections::BTreeMap<u64, String>,
+}
+
+// what became of a response once the image store has been updated, see `persist_response`
+enum PersistedResponse {
+    /// with the stored copy, in case it isn't in memory anymore
+    NotModified(Option<CachedImageResponse>),
+    Modified(CachedImageResponse),
+}
+
+fn load_from_store(store: &ImageStore, uri: &str) -> Option<CachedImageResponse> {
+    let (bytes, meta) = store.load(uri)?;
+    Some(CachedImageResponse {
+        t: meta.fetched_at,
+        validators: meta.validators,
+        ..CachedImageResponse::from_bytes(bytes)
+    })
+}
+
+fn store_response(
+    store: Option<&ImageStore>,
+    uri: &str,
+    bytes: Vec<u8>,
+    validators: Validators,
+) -> Result<CachedImageResponse> {
+    // don't keep bytes around that can't be rendered later on
+    decode_image(&bytes)?;
+    let t = match store {
+        Some(store) => match store.store(uri, &bytes, &validators) {
+            Ok(meta) => meta.fetched_at,
+            Err(e) => {
+                error!("couldn't store image from uri {}: {}", uri, e);
+                OffsetDateTime::now_utc()
+            }
+        },
+        None => OffsetDateTime::now_utc(),
+    };
+    Ok(CachedImageResponse {
+        t,
+        validators,
+        ..CachedImageResponse::from_bytes(bytes)
+    })
+}
+
+// the part of taking a response which reads and writes files, so it can run without the cache
+fn persist_response(
+    store: Option<&ImageStore>,
+    uri: &str,
+    response: Result<ImageResponse>,
+) -> Result<PersistedResponse> {
+    match response? {
+        ImageResponse::NotModified => Ok(PersistedResponse::NotModified(store.and_then(|store| {
+            if let Err(e) = store.refresh(uri) {
+                error!("couldn't refresh stored image from uri {}: {}", uri, e);
+            }
+            load_from_store(store, uri)
+        }))),
+        ImageResponse::Modified { bytes, validators } => {
+            store_response(store, uri, bytes, validators).map(PersistedResponse::Modified)
+        }
+    }
+}
+
+// file access of the image store blocks, so it runs on its own thread and not on the executor
+async fn run_blocking<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
 }
 
 impl ScryfallCache {
+    /// How long an image is used without asking the server whether it changed.
     fn get_max_age() -> time::Duration {
         time::Duration::days(14)
     }
@@ -296,47 +871,195 @@ impl ScryfallCache {
         ScryfallCache {
             last_purge: OffsetDateTime::now_utc(),
             images: std::collections::HashMap::new(),
+            store: None,
+            budget: CacheBudget::default(),
+            metrics: CacheMetrics::default(),
+            total_bytes: 0,
+            tick: 0,
+            recency: std::collections::BTreeMap::new(),
+        }
+    }
+
+    pub fn with_store(store: ImageStore) -> ScryfallCache {
+        ScryfallCache {
+            store: Some(Arc::new(store)),
+            ..ScryfallCache::new()
         }
     }
 
-    pub async fn ensure_contains(&mut self, uri: &str, client: &ScryfallClient) -> Option<()> {
-        let entry = self.images.entry(uri.to_string());
-        match entry {
-            Occupied(_) => {
-                debug!("image uri cached: {}", uri);
-                Some(())
+    pub fn set_budget(&mut self, budget: CacheBudget) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    pub fn metrics(&self) -> CacheMetrics {
+        self.metrics
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    fn touch(&mut self, uri: &str) {
+        if let Some(cached) = self.images.get_mut(uri) {
+            self.recency.remove(&cached.last_used);
+            self.tick += 1;
+            cached.last_used = self.tick;
+            self.recency.insert(self.tick, uri.to_string());
+        }
+    }
+
+    fn over_budget(&self) -> bool {
+        self.budget
+            .max_bytes
+            .is_some_and(|max| self.total_bytes > max)
+            || self
+                .budget
+                .max_entries
+                .is_some_and(|max| self.images.len() > max)
+    }
+
+    // the most recently used image is kept even if it exceeds the budget on its own,
+    // otherwise it would be gone before it can be rendered
+    fn evict(&mut self) {
+        while self.images.len() > 1 && self.over_budget() {
+            let Some((_, uri)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = self.images.remove(&uri) {
+                debug!("evicting image uri {}", uri);
+                self.total_bytes -= evicted.bytes.len();
+                self.metrics.evictions += 1;
             }
-            Vacant(token) => {
-                let image_query = query_image_uri(uri, client).await;
-                match image_query {
-                    Some(image) => {
-                        token.insert(CachedImageResponse {
-                            t: OffsetDateTime::now_utc(),
-                            image,
-                        });
-                        Some(())
-                    }
+        }
+    }
+
+    fn remove(&mut self, uri: &str) {
+        if let Some(removed) = self.images.remove(uri) {
+            self.recency.remove(&removed.last_used);
+            self.total_bytes -= removed.bytes.len();
+        }
+    }
+
+    fn insert(&mut self, uri: &str, response: CachedImageResponse) {
+        self.remove(uri);
+        self.total_bytes += response.bytes.len();
+        self.images.insert(uri.to_string(), response);
+        self.touch(uri);
+        self.evict();
+    }
+
+    // `None` if the image isn't in memory and the image store has to be asked
+    fn lookup_memory(&mut self, uri: &str) -> Option<CacheLookup> {
+        let cached = self.images.get(uri)?;
+        let lookup = if cached.is_fresh() {
+            debug!("image uri cached: {}", uri);
+            self.metrics.hits += 1;
+            CacheLookup::Fresh
+        } else {
+            self.metrics.revalidations += 1;
+            CacheLookup::Stale(cached.validators.clone())
+        };
+        self.touch(uri);
+        Some(lookup)
+    }
+
+    fn lookup_stored(&mut self, uri: &str, stored: Option<CachedImageResponse>) -> CacheLookup {
+        // another request may have put it into memory in the meantime
+        if let Some(lookup) = self.lookup_memory(uri) {
+            return lookup;
+        }
+        match stored {
+            Some(stored) => {
+                let lookup = if stored.is_fresh() {
+                    debug!("image uri loaded from store: {}", uri);
+                    self.metrics.store_hits += 1;
+                    CacheLookup::Fresh
+                } else {
+                    self.metrics.revalidations += 1;
+                    CacheLookup::Stale(stored.validators.clone())
+                };
+                self.insert(uri, stored);
+                lookup
+            }
+            None => {
+                self.metrics.misses += 1;
+                CacheLookup::Missing
+            }
+        }
+    }
+
+    /// Takes the response to the request made after the lookup, a stale copy stays in use
+    /// if the server can't be reached.
+    fn apply_persisted(&mut self, uri: &str, persisted: Result<PersistedResponse>) -> Result<()> {
+        let outcome = match persisted {
+            Ok(PersistedResponse::NotModified(stored)) => {
+                debug!("image uri not modified: {}", uri);
+                self.metrics.not_modified += 1;
+                match self.images.get_mut(uri) {
+                    Some(cached) => cached.t = OffsetDateTime::now_utc(),
+                    // it may have been evicted while the request was running
                     None => {
-                        error!("calling image from uri {} failed", uri);
-                        None
+                        if let Some(stored) = stored {
+                            self.insert(uri, stored);
+                        }
                     }
                 }
+                Ok(())
+            }
+            Ok(PersistedResponse::Modified(fetched)) => {
+                self.insert(uri, fetched);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        };
+        if self.images.contains_key(uri) {
+            if let Err(e) = outcome {
+                info!("keeping stale image {}: {}", uri, e);
             }
+            Ok(())
+        } else {
+            error!("calling image from uri {} failed", uri);
+            outcome.and(Err(Error::MissingImage(uri.to_string())))
         }
     }
 
-    pub async fn ensure_contains_line(&mut self, line: &ImageLine, client: &ScryfallClient) {
-        for (uri, _mult) in &line.images {
-            self.ensure_contains(uri, client).await;
-        }
+    /// Decodes the cached image, this counts as a use for the eviction order.
+    pub fn get(&mut self, uri: &str) -> Option<DynamicImage> {
+        self.touch(uri);
+        decode_image(&self.images.get(uri)?.bytes)
+            .inspect_err(|e| error!("cached image {}: {}", uri, e))
+            .ok()
     }
 
-    pub fn get(&self, uri: &str) -> Option<&DynamicImage> {
-        self.images.get(uri).map(|ci| &ci.image)
+    pub fn render(&mut self, image: &CardImage) -> Option<DynamicImage> {
+        self.get(&image.uri)
+            .map(|decoded| image.section.extract(&decoded).into_owned())
     }
 
-    pub fn list(&self) -> String {
+    // `stored` is the number of images in the image store
+    fn describe(&self, stored: Option<usize>) -> String {
         let mut desc: String = format!("last purged at {}", self.last_purge);
+        desc += &format!(
+            ", {} images using {} bytes in memory (budget: {:?} bytes, {:?} images)",
+            self.images.len(),
+            self.total_bytes,
+            self.budget.max_bytes,
+            self.budget.max_entries
+        );
+        desc += &format!(
+            ", {} hits, {} store hits, {} misses, {} revalidations ({} not modified), {} evictions, {} deduplicated",
+            self.metrics.hits,
+            self.metrics.store_hits,
+            self.metrics.misses,
+            self.metrics.revalidations,
+            self.metrics.not_modified,
+            self.metrics.evictions,
+            self.metrics.deduplicated
+        );
+        if let (Some(store), Some(stored)) = (&self.store, stored) {
+            desc += &format!(", {} images stored in {:?}", stored, store.dir());
+        }
         desc += "<table>\n<tbody>";
         for (key, value) in &self.images {
             desc.push_str(format!("<tr><td>{}</td><td>{:?}</td></tr>\n", key, value).as_str());
@@ -345,13 +1068,23 @@ impl ScryfallCache {
         desc
     }
 
-    pub fn purge(&mut self, max_age: Option<time::Duration>) {
+    // returns the maximum age to purge the image store with
+    fn purge_memory(&mut self, explicit_age: Option<time::Duration>) -> time::Duration {
         let n = OffsetDateTime::now_utc();
+        let max_age = explicit_age.unwrap_or_else(ScryfallCache::get_max_age);
         debug!("{} cached responses before purging", self.images.len());
-        self.images
-            .retain(|_, value| n - value.t < max_age.unwrap_or_else(ScryfallCache::get_max_age));
+        let expired: Vec<String> = self
+            .images
+            .iter()
+            .filter(|(_, value)| n - value.t >= max_age)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for uri in expired {
+            self.remove(&uri);
+        }
         self.last_purge = n;
         debug!("{} cached responses after purging", self.images.len());
+        max_age
     }
 }
 
@@ -361,6 +1094,266 @@ impl Default for ScryfallCache {
     }
 }
 
+// enough to keep the cooldown of the scryfall client busy without piling up requests
+const MAX_CONCURRENT_DOWNLOADS: usize = 8;
+
+type InFlight = std::collections::HashMap<String, Arc<tokio::sync::OnceCell<Result<()>>>>;
+
+/// The image cache shared between requests. The lock is only held for bookkeeping and not while
+/// downloading or accessing the image store, concurrent requests for the same uri wait for a
+/// single download.
+pub struct SharedImageCache {
+    cache: tokio::sync::Mutex<ScryfallCache>,
+    in_flight: std::sync::Mutex<InFlight>,
+}
+
+impl SharedImageCache {
+    pub fn new(cache: ScryfallCache) -> SharedImageCache {
+        SharedImageCache {
+            cache: tokio::sync::Mutex::new(cache),
+            in_flight: std::sync::Mutex::new(InFlight::new()),
+        }
+    }
+
+    pub async fn lock(&self) -> tokio::sync::MutexGuard<'_, ScryfallCache> {
+        self.cache.lock().await
+    }
+
+    /// Looks for the image in memory and then in the image store, without going to the network.
+    async fn lookup(&self, uri: &str) -> CacheLookup {
+        let store = {
+            let mut cache = self.cache.lock().await;
+            if let Some(lookup) = cache.lookup_memory(uri) {
+                return lookup;
+            }
+            cache.store.clone()
+        };
+        let stored = match store {
+            Some(store) => {
+                let uri = uri.to_string();
+                run_blocking(move || load_from_store(&store, &uri)).await
+            }
+            None => None,
+        };
+        self.cache.lock().await.lookup_stored(uri, stored)
+    }
+
+    async fn download(&self, uri: &str, source: &dyn ImageSource) -> Result<()> {
+        let validators = match self.lookup(uri).await {
+            CacheLookup::Fresh => return Ok(()),
+            CacheLookup::Stale(validators) => validators,
+            CacheLookup::Missing => Validators::default(),
+        };
+        let response = source.image(uri, &validators).await;
+        let store = self.cache.lock().await.store.clone();
+        let persisted = {
+            let uri = uri.to_string();
+            run_blocking(move || persist_response(store.as_deref(), &uri, response)).await
+        };
+        self.cache.lock().await.apply_persisted(uri, persisted)
+    }
+
+    pub async fn ensure_contains(&self, uri: &str, source: &dyn ImageSource) -> Result<()> {
+        let cell = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(uri.to_string())
+            .or_default()
+            .clone();
+        let mut initialized = false;
+        let result = cell
+            .get_or_init(|| async {
+                initialized = true;
+                let result = self.download(uri, source).await;
+                // later requests find the image in the cache
+                self.in_flight.lock().unwrap().remove(uri);
+                result
+            })
+            .await
+            .clone();
+        if !initialized {
+            self.cache.lock().await.metrics.deduplicated += 1;
+        }
+        result
+    }
+
+    /// Makes sure that all images of the lines are cached, downloading several at once.
+    pub async fn ensure_contains_lines(
+        &self,
+        lines: &[ImageLine],
+        source: &dyn ImageSource,
+    ) -> Result<()> {
+        let uris: std::collections::HashSet<&str> = lines
+            .iter()
+            .flat_map(|line| line.images.iter())
+            .map(|(image, _)| image.uri.as_str())
+            .collect();
+        // collected up front, mapping the stream instead trips up the Send check for rocket routes
+        let downloads: Vec<_> = uris
+            .into_iter()
+            .map(|uri| self.ensure_contains(uri, source))
+            .collect();
+        rocket::futures::stream::iter(downloads)
+            .buffer_unordered(MAX_CONCURRENT_DOWNLOADS)
+            .collect::<Vec<Result<()>>>()
+            .await
+            .into_iter()
+            .collect()
+    }
+
+    pub async fn list(&self) -> String {
+        let store = self.cache.lock().await.store.clone();
+        let stored = match store {
+            Some(store) => Some(run_blocking(move || store.entries().len()).await),
+            None => None,
+        };
+        self.cache.lock().await.describe(stored)
+    }
+
+    pub async fn purge(&self, explicit_age: Option<time::Duration>) {
+        let (store, max_age) = {
+            let mut cache = self.cache.lock().await;
+            (cache.store.clone(), cache.purge_memory(explicit_age))
+        };
+        if let Some(store) = store {
+            // on the regular purge keep what can be revalidated instead of downloaded again
+            run_blocking(move || store.purge(max_age, explicit_age.is_none())).await;
+        }
+    }
+
+    /// Renders the image, downloading it again if it has been evicted in the meantime.
+    pub async fn render(
+        &self,
+        image: &CardImage,
+        source: &dyn ImageSource,
+    ) -> Result<DynamicImage> {
+        if let Some(rendered) = self.cache.lock().await.render(image) {
+            return Ok(rendered);
+        }
+        self.ensure_contains(&image.uri, source).await?;
+        self.cache
+            .lock()
+            .await
+            .render(image)
+            .ok_or_else(|| Error::MissingImage(image.uri.clone()))
+    }
+}
+
+/// The image lines of a decklist, including the tokens of the cards if asked for.
+pub async fn proxy_image_lines(
+    decklist: &str,
+    card_data: &mut CardData,
+    options: ProxyOptions,
+    source: &dyn CardSource,
+) -> Result<Vec<ImageLine>> {
+    let parsed = parse_decklist(decklist, &card_data.printings.languages);
+    let mut lines = image_lines_from_decklist(
+        parsed,
+        card_data,
+        options.backside,
+        options.meld,
+        options.policy,
+        source,
+    )
+    .await?;
+    if options.tokens {
+        let token_lines = card_data
+            .get_related_tokens(&lines, options.policy, source)
+            .await?;
+        lines.extend(token_lines);
+    }
+    Ok(lines)
+}
+
+/// A card image as it is printed and how often it is printed.
+pub struct RenderedCard {
+    pub name: String,
+    pub image: DynamicImage,
+    pub multiplicity: i32,
+}
+
+// each image once, with the multiplicities of all lines it appears in added up
+fn distinct_images(lines: &[ImageLine]) -> Vec<(&CardImage, i32)> {
+    let mut distinct: Vec<(&CardImage, i32)> = Vec::new();
+    for (image, multiplicity) in lines.iter().flat_map(|line| &line.images) {
+        match distinct.iter_mut().find(|(known, _)| *known == image) {
+            Some((_, known_multiplicity)) => *known_multiplicity += multiplicity,
+            None => distinct.push((image, *multiplicity)),
+        }
+    }
+    distinct
+}
+
+/// How many images `render_cards` renders for the lines.
+pub fn card_count(lines: &[ImageLine]) -> u64 {
+    distinct_images(lines)
+        .iter()
+        .filter(|(_, multiplicity)| *multiplicity > 0)
+        .count() as u64
+}
+
+/// How many pages `render_pages` composes for the lines.
+pub fn page_count(lines: &[ImageLine]) -> u64 {
+    let cards: u64 = lines
+        .iter()
+        .flat_map(|line| &line.images)
+        .map(|(_, multiplicity)| u64::try_from(*multiplicity).unwrap_or(0))
+        .sum();
+    cards.div_ceil(9)
+}
+
+/// Downloads and renders each distinct image of the lines once, e.g. for print services which
+/// take one file per card and how often to print it.
+pub async fn render_cards(
+    lines: &[ImageLine],
+    image_cache: &SharedImageCache,
+    source: &dyn ImageSource,
+) -> Result<Vec<RenderedCard>> {
+    image_cache.ensure_contains_lines(lines, source).await?;
+
+    let mut cards = Vec::new();
+    for (image, multiplicity) in distinct_images(lines) {
+        if multiplicity > 0 {
+            cards.push(RenderedCard {
+                name: image.name.clone(),
+                image: image_cache.render(image, source).await?,
+                multiplicity,
+            });
+        }
+    }
+
+    if cards.is_empty() {
+        return Err(Error::EmptyDecklist);
+    }
+    Ok(cards)
+}
+
+/// Downloads the images of the lines and composes them into pages of 3x3 cards, each image as
+/// often as its multiplicity.
+pub async fn render_pages(
+    lines: &[ImageLine],
+    image_cache: &SharedImageCache,
+    source: &dyn ImageSource,
+) -> Result<Vec<DynamicImage>> {
+    image_cache.ensure_contains_lines(lines, source).await?;
+
+    let mut expanded: Vec<DynamicImage> = Vec::new();
+    for line in lines.iter() {
+        for (image, multiplicity) in &line.images {
+            let rendered = image_cache.render(image, source).await?;
+            for _i in 0..*multiplicity {
+                expanded.push(rendered.clone());
+            }
+        }
+    }
+
+    if expanded.is_empty() {
+        return Err(Error::EmptyDecklist);
+    }
+    Ok(expanded.iter().batching(|it| images_to_page(it)).collect())
+}
+
 pub fn images_to_page<'a, I>(mut it: I) -> Option<DynamicImage>
 where
     I: Iterator<Item = &'a DynamicImage>,
@@ -398,3 +1391,556 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printing(set: &str, language: &str, digital: bool) -> MinimalScryfallObject {
+        let serialized = format!(
+            r#"{{"name":"lightning bolt","set":"{}","language":"{}","faces":[{{"name":"lightning bolt","border_crop":"https://cards.scryfall.io/border_crop/front/{}.jpg"}}],"meld_result":null,"digital":{}}}"#,
+            set, language, set, digital
+        );
+        serde_json::from_str(&serialized).unwrap()
+    }
+
+    #[test]
+    fn digital_printings_skipped_by_default() {
+        let printings = vec![printing("ha1", "en", true), printing("m10", "en", false)];
+        let entry = DecklistEntry::from_name("lightning bolt");
+        let selected = select_printing(&printings, &entry, PrintingPolicy::default()).unwrap();
+        assert_eq!(selected.set, "m10");
+    }
+
+    #[test]
+    fn digital_printings_skipped_even_if_set_matches() {
+        let printings = vec![printing("ha1", "en", true), printing("m10", "en", false)];
+        let entry = DecklistEntry::new(1, "lightning bolt", Some("ha1"), None);
+        let selected = select_printing(&printings, &entry, PrintingPolicy::default()).unwrap();
+        assert_eq!(selected.set, "m10");
+    }
+
+    #[test]
+    fn digital_printings_allowed() {
+        let printings = vec![printing("ha1", "en", true), printing("m10", "en", false)];
+        let entry = DecklistEntry::new(1, "lightning bolt", Some("ha1"), None);
+        let policy = PrintingPolicy {
+            allow_digital: true,
+        };
+        let selected = select_printing(&printings, &entry, policy).unwrap();
+        assert_eq!(selected.set, "ha1");
+    }
+
+    #[test]
+    fn only_digital_printings() {
+        let printings = vec![printing("ha1", "en", true)];
+        let entry = DecklistEntry::from_name("lightning bolt");
+        assert!(select_printing(&printings, &entry, PrintingPolicy::default()).is_none());
+    }
+
+    fn meld_printings() -> Vec<MinimalScryfallObject> {
+        let input = include_str!("../test_input/default-cards_meld.json");
+        let list: Vec<serde_json::Map<String, serde_json::Value>> =
+            serde_json::from_str(input).unwrap();
+        list.iter()
+            .filter_map(MinimalScryfallObject::from_dict)
+            .collect()
+    }
+
+    fn meld_printing(name: &str, collector_number: &str) -> MinimalScryfallObject {
+        meld_printings()
+            .into_iter()
+            .find(|p| p.name == name && p.collector_number.as_deref() == Some(collector_number))
+            .unwrap()
+    }
+
+    #[test]
+    fn meld_halves() {
+        let results: Vec<MinimalScryfallObject> = meld_printings()
+            .into_iter()
+            .filter(|p| p.name == "urza, planeswalker")
+            .collect();
+        let mightstone = meld_printing("the mightstone and weakstone", "238a");
+        let urza = meld_printing("urza, lord protector", "225");
+        let policy = PrintingPolicy::default();
+        let top = meld_back_image(&mightstone, &results, MeldMode::Halves, policy).unwrap();
+        let bottom = meld_back_image(&urza, &results, MeldMode::Halves, policy).unwrap();
+        assert_eq!(top.section, ImageSection::TopHalf);
+        assert_eq!(bottom.section, ImageSection::BottomHalf);
+        assert_eq!(top.uri, bottom.uri);
+        assert_eq!(
+            Some(top.uri),
+            meld_printing("urza, planeswalker", "238b")
+                .front()
+                .map(|face| face.border_crop.clone())
+        );
+    }
+
+    #[test]
+    fn meld_result_same_variant() {
+        let results: Vec<MinimalScryfallObject> = meld_printings()
+            .into_iter()
+            .filter(|p| p.name == "urza, planeswalker")
+            .collect();
+        let urza_prerelease = meld_printing("urza, lord protector", "225s");
+        let whole = meld_back_image(
+            &urza_prerelease,
+            &results,
+            MeldMode::Whole,
+            PrintingPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(whole.section, ImageSection::Full);
+        assert_eq!(
+            Some(whole.uri),
+            meld_printing("urza, planeswalker", "238bs")
+                .front()
+                .map(|face| face.border_crop.clone())
+        );
+    }
+
+    #[test]
+    fn meld_half_has_card_dimensions() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(IMAGE_WIDTH, IMAGE_HEIGHT));
+        let half = ImageSection::BottomHalf.extract(&image);
+        assert_eq!(half.width(), IMAGE_WIDTH);
+        assert_eq!(half.height(), IMAGE_HEIGHT);
+    }
+
+    #[test]
+    fn shared_images_printed_once() {
+        let result = CardImage {
+            name: "urza, planeswalker".to_string(),
+            uri: "https://cards.scryfall.io/border_crop/front/4/0/result.jpg".to_string(),
+            section: ImageSection::Full,
+        };
+        let part = |name: &str, mult: i32| ImageLine {
+            name: name.to_string(),
+            images: Vec::new(),
+            shared_images: vec![(result.clone(), mult)],
+            tokens: Vec::new(),
+        };
+        let merged = merge_shared_images(&[
+            part("urza, lord protector", 2),
+            part("the mightstone and weakstone", 1),
+        ]);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].images, vec![(result, 2)]);
+    }
+
+    #[test]
+    fn single_face_ignores_backside_mode() {
+        assert_eq!(face_multiplicities(1, BacksideMode::BackOnly, None, 3), [3]);
+        assert_eq!(face_multiplicities(1, BacksideMode::Zero, Some(1), 3), [3]);
+    }
+
+    #[test]
+    fn double_faced() {
+        assert_eq!(face_multiplicities(2, BacksideMode::Zero, None, 3), [3, 0]);
+        assert_eq!(face_multiplicities(2, BacksideMode::One, None, 3), [3, 1]);
+        assert_eq!(
+            face_multiplicities(2, BacksideMode::Matching, None, 3),
+            [3, 3]
+        );
+        assert_eq!(
+            face_multiplicities(2, BacksideMode::BackOnly, None, 3),
+            [0, 3]
+        );
+    }
+
+    #[test]
+    fn more_than_two_faces() {
+        assert_eq!(
+            face_multiplicities(3, BacksideMode::One, None, 2),
+            [2, 1, 1]
+        );
+        assert_eq!(
+            face_multiplicities(3, BacksideMode::Zero, Some(2), 2),
+            [0, 0, 2]
+        );
+    }
+
+    fn jpeg_bytes() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::new_rgb8(4, 4)
+            .write_to(
+                &mut std::io::Cursor::new(&mut bytes),
+                printpdf::image_crate::ImageOutputFormat::Jpeg(90),
+            )
+            .unwrap();
+        bytes
+    }
+
+    fn cached(bytes: usize) -> CachedImageResponse {
+        CachedImageResponse::from_bytes(vec![0; bytes])
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ScryfallCache::new();
+        cache.set_budget(CacheBudget {
+            max_bytes: Some(250),
+            max_entries: None,
+        });
+        cache.insert("a", cached(100));
+        cache.insert("b", cached(100));
+        cache.touch("a");
+        cache.insert("c", cached(100));
+        assert!(cache.images.contains_key("a"));
+        assert!(!cache.images.contains_key("b"));
+        assert!(cache.images.contains_key("c"));
+        assert_eq!(cache.total_bytes(), 200);
+        assert_eq!(cache.metrics().evictions, 1);
+    }
+
+    #[test]
+    fn entry_budget() {
+        let mut cache = ScryfallCache::new();
+        for uri in ["a", "b", "c"] {
+            cache.insert(uri, cached(10));
+        }
+        cache.set_budget(CacheBudget {
+            max_bytes: None,
+            max_entries: Some(1),
+        });
+        assert_eq!(cache.images.len(), 1);
+        assert!(cache.images.contains_key("c"));
+        assert_eq!(cache.total_bytes(), 10);
+        assert_eq!(cache.recency.len(), 1);
+    }
+
+    #[test]
+    fn oversized_image_is_kept() {
+        let mut cache = ScryfallCache::new();
+        cache.set_budget(CacheBudget {
+            max_bytes: Some(50),
+            max_entries: None,
+        });
+        cache.insert("a", cached(10));
+        cache.insert("b", cached(100));
+        assert_eq!(cache.images.len(), 1);
+        assert!(cache.images.contains_key("b"));
+    }
+
+    #[test]
+    fn replacing_keeps_accounting() {
+        let mut cache = ScryfallCache::new();
+        cache.insert("a", cached(10));
+        cache.insert("a", cached(30));
+        assert_eq!(cache.total_bytes(), 30);
+        assert_eq!(cache.recency.len(), 1);
+        cache.purge_memory(Some(time::Duration::ZERO));
+        assert_eq!(cache.total_bytes(), 0);
+        assert!(cache.recency.is_empty());
+    }
+
+    #[test]
+    fn decodes_compressed_images() {
+        let mut cache = ScryfallCache::new();
+        cache.insert("a", CachedImageResponse::from_bytes(jpeg_bytes()));
+        let decoded = cache.get("a").unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (4, 4));
+        assert!(cache.get("b").is_none());
+    }
+
+    #[tokio::test]
+    async fn hits_and_store_hits() {
+        let dir =
+            std::env::temp_dir().join(format!("magichawk-cache-metrics-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = ImageStore::new(&dir).unwrap();
+        store
+            .store("a", &jpeg_bytes(), &Validators::default())
+            .unwrap();
+        let cache = SharedImageCache::new(ScryfallCache::with_store(store));
+        assert_eq!(cache.lookup("a").await, CacheLookup::Fresh);
+        assert_eq!(cache.lookup("a").await, CacheLookup::Fresh);
+        assert_eq!(cache.lookup("b").await, CacheLookup::Missing);
+        let metrics = cache.lock().await.metrics();
+        assert_eq!(
+            (metrics.hits, metrics.store_hits, metrics.misses),
+            (1, 1, 1)
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stale_images_need_revalidation() {
+        let cache = SharedImageCache::new(ScryfallCache::new());
+        cache.lock().await.insert(
+            "a",
+            CachedImageResponse {
+                t: OffsetDateTime::now_utc() - time::Duration::days(15),
+                ..cached(10)
+            },
+        );
+        assert_eq!(
+            cache.lookup("a").await,
+            CacheLookup::Stale(Validators::default())
+        );
+        assert_eq!(cache.lock().await.metrics().hits, 0);
+        let persisted = persist_response(None, "a", Ok(ImageResponse::NotModified));
+        assert!(cache.lock().await.apply_persisted("a", persisted).is_ok());
+        assert_eq!(cache.lookup("a").await, CacheLookup::Fresh);
+        assert_eq!(cache.lock().await.metrics().not_modified, 1);
+    }
+
+    #[test]
+    fn validator_headers() {
+        let validators = Validators {
+            etag: Some("\"abc\"".to_string()),
+            last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+        };
+        let headers = conditional_headers(&validators);
+        assert_eq!(headers[reqwest::header::IF_NONE_MATCH], "\"abc\"");
+        assert_eq!(
+            headers[reqwest::header::IF_MODIFIED_SINCE],
+            "Wed, 21 Oct 2015 07:28:00 GMT"
+        );
+        let mut response_headers = reqwest::header::HeaderMap::new();
+        response_headers.insert(reqwest::header::ETAG, "\"abc\"".parse().unwrap());
+        response_headers.insert(
+            reqwest::header::LAST_MODIFIED,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(validators_from_headers(&response_headers), validators);
+        assert!(conditional_headers(&Validators::default()).is_empty());
+    }
+
+    // serves the same jpeg after a short delay for every connection and counts them
+    async fn serve_jpeg(connections: Arc<std::sync::atomic::AtomicUsize>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                connections.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut request = [0u8; 4096];
+                    let _ = socket.read(&mut request).await;
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    let body = jpeg_bytes();
+                    let header = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    socket.write_all(header.as_bytes()).await.unwrap();
+                    socket.write_all(&body).await.unwrap();
+                });
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    // answers every connection with the same response without a body
+    async fn serve_status(response: &'static str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 4096];
+                let _ = socket.read(&mut request).await;
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn image_status_errors() {
+        let client = ScryfallClient::new()
+            .with_rate_limiter(Arc::new(RateLimiter::new(std::time::Duration::ZERO, 1)))
+            .with_retry_policy(RetryPolicy {
+                max_retries: 0,
+                ..RetryPolicy::default()
+            });
+        let not_found = serve_status(
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        let missing = query_image_bytes(&format!("{}/a.jpg", not_found), &client).await;
+        assert!(matches!(missing, Err(Error::MissingImage(_))));
+        let unavailable = serve_status(
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        let outage = query_image_bytes(&format!("{}/a.jpg", unavailable), &client)
+            .await
+            .unwrap_err();
+        assert!(matches!(outage, Error::Network(_)));
+        assert_eq!(outage.status(), rocket::http::Status::BadGateway);
+    }
+
+    fn image_line(uris: &[String]) -> ImageLine {
+        ImageLine {
+            name: "test".to_string(),
+            images: uris
+                .iter()
+                .map(|uri| {
+                    let image = CardImage {
+                        name: "test".to_string(),
+                        uri: uri.clone(),
+                        section: ImageSection::Full,
+                    };
+                    (image, 1)
+                })
+                .collect(),
+            shared_images: Vec::new(),
+            tokens: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_a_download() {
+        let connections = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let uri = format!("{}/a.jpg", serve_jpeg(connections.clone()).await);
+        let client = ScryfallClient::new();
+        let cache = SharedImageCache::new(ScryfallCache::new());
+        let (first, second) = tokio::join!(
+            cache.ensure_contains(&uri, &client),
+            cache.ensure_contains(&uri, &client)
+        );
+        assert!(first.is_ok() && second.is_ok());
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 1);
+        let metrics = cache.lock().await.metrics();
+        assert_eq!((metrics.misses, metrics.deduplicated), (1, 1));
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn prefetch_lines() {
+        let connections = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let base = serve_jpeg(connections.clone()).await;
+        let uris: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .map(|name| format!("{}/{}.jpg", base, name))
+            .collect();
+        let lines = [image_line(&uris), image_line(&uris[..1])];
+        let client = ScryfallClient::new();
+        let cache = SharedImageCache::new(ScryfallCache::new());
+        cache.ensure_contains_lines(&lines, &client).await.unwrap();
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 3);
+        for (image, _) in &lines[0].images {
+            assert!(cache.render(image, &client).await.is_ok());
+        }
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn distinct_images_add_up() {
+        let uris: Vec<String> = ["a", "b"].iter().map(|name| name.to_string()).collect();
+        let lines = [image_line(&uris), image_line(&uris[..1])];
+        let multiplicities: Vec<(&str, i32)> = distinct_images(&lines)
+            .into_iter()
+            .map(|(image, multiplicity)| (image.uri.as_str(), multiplicity))
+            .collect();
+        assert_eq!(multiplicities, [("a", 2), ("b", 1)]);
+        assert_eq!(card_count(&lines), 2);
+        assert_eq!(page_count(&lines), 1);
+        let mut many = image_line(&uris[..1]);
+        many.images[0].1 = 10;
+        assert_eq!(page_count(&[many]), 2);
+    }
+
+    #[tokio::test]
+    async fn decklist_from_fixture() {
+        let objects: Vec<serde_json::Map<String, serde_json::Value>> =
+            serde_json::from_str(include_str!("../test_input/default-cards_meld.json")).unwrap();
+        let mut source = FixtureSource::new().with_cards(objects);
+        let mut card_data = CardData::from_printings(CardPrintings::new(), &source)
+            .await
+            .unwrap();
+        let parsed = parse_decklist(
+            "2 urza, lord protector (bro)\n1 nonexistent card",
+            &card_data.printings.languages,
+        );
+        let lines = image_lines_from_decklist(
+            parsed,
+            &mut card_data,
+            BacksideMode::Matching,
+            MeldMode::Whole,
+            PrintingPolicy::default(),
+            &source,
+        )
+        .await
+        .unwrap();
+        // the card itself and the meld result shared by both parts
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].images.len(), 1);
+        assert_eq!(lines[0].images[0].1, 2);
+        assert_eq!(lines[1].name, "urza, planeswalker");
+
+        for line in &lines {
+            for (image, _) in &line.images {
+                source = source.with_image(&image.uri, jpeg_bytes());
+            }
+        }
+        let cache = SharedImageCache::new(ScryfallCache::new());
+        cache.ensure_contains_lines(&lines, &source).await.unwrap();
+        assert!(cache.render(&lines[1].images[0].0, &source).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn four_letter_note_is_no_set() {
+        // promo sets have four letters, so a note of that length is taken as a set and ignored
+        // when no printing is from it
+        let objects: Vec<serde_json::Map<String, serde_json::Value>> =
+            serde_json::from_str(include_str!("../test_input/default-cards_meld.json")).unwrap();
+        let source = FixtureSource::new().with_cards(objects);
+        let mut card_data = CardData::from_printings(CardPrintings::new(), &source)
+            .await
+            .unwrap();
+        let parsed = parse_decklist(
+            "1 urza, lord protector (foil)",
+            &card_data.printings.languages,
+        );
+        assert_eq!(parsed[0].as_entry().unwrap().set.as_deref(), Some("foil"));
+        let lines = image_lines_from_decklist(
+            parsed,
+            &mut card_data,
+            BacksideMode::Matching,
+            MeldMode::Whole,
+            PrintingPolicy::default(),
+            &source,
+        )
+        .await
+        .unwrap();
+        assert_eq!(lines[0].name, "urza, lord protector");
+        assert_eq!(lines[0].images.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn shared_cache_uses_the_store() {
+        let dir =
+            std::env::temp_dir().join(format!("magichawk-shared-store-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = ImageStore::new(&dir).unwrap();
+        store
+            .store("a", &jpeg_bytes(), &Validators::default())
+            .unwrap();
+        let cache = SharedImageCache::new(ScryfallCache::with_store(store));
+        // nothing can be downloaded from the fixture, so the image has to come from the store
+        cache
+            .ensure_contains("a", &FixtureSource::new())
+            .await
+            .unwrap();
+        assert_eq!(cache.lock().await.metrics().store_hits, 1);
+        assert!(cache.list().await.contains("1 images stored"));
+        cache.purge(Some(time::Duration::ZERO)).await;
+        assert!(cache.list().await.contains("0 images stored"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_image_is_an_error() {
+        let cache = SharedImageCache::new(ScryfallCache::new());
+        let source = FixtureSource::new();
+        assert!(matches!(
+            cache.ensure_contains("a", &source).await,
+            Err(Error::MissingImage(_))
+        ));
+    }
+}
@@ -2,7 +2,6 @@ extern crate ngrammatic;
 
 use log::debug;
 use ngrammatic::{Corpus, CorpusBuilder};
-use ord_subset::OrdVar;
 use std::collections::HashMap;
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone, Copy, PartialOrd, Ord)]
@@ -17,9 +16,9 @@ pub struct NameLookupResult {
     pub hit: NameMatchMode,
 }
 
-#[derive(Debug, Clone, PartialOrd, Ord, Eq, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 struct CorpusLookupResult {
-    similarity: OrdVar<f32>,
+    similarity: f32,
     name: String,
 }
 
@@ -53,9 +52,12 @@ impl CardCorpus {
             .search(name, CardCorpus::THRESHOLD)
             .into_iter()
             .next()?;
+        if n.similarity.is_nan() {
+            return None;
+        }
         Some(CorpusLookupResult {
             name: self.to_full.get(n.text.as_str()).unwrap_or(&n.text).clone(),
-            similarity: OrdVar::new_checked(n.similarity)?,
+            similarity: n.similarity,
         })
     }
 }
@@ -80,7 +82,7 @@ impl CardNameLookup {
         lookup
     }
 
-    fn insert(&mut self, name_uppercase: &str) {
+    pub(crate) fn insert(&mut self, name_uppercase: &str) {
         let name = name_uppercase.to_lowercase();
         self.corpora
             .entry(NameMatchMode::Full)
@@ -103,7 +105,9 @@ impl CardNameLookup {
             .corpora
             .iter()
             .filter_map(|(mode, c)| Some((c.find(&name)?, *mode)))
-            .max_by(|(leftres, _), (rightres, _)| leftres.similarity.cmp(&rightres.similarity))?;
+            .max_by(|(leftres, _), (rightres, _)| {
+                leftres.similarity.total_cmp(&rightres.similarity)
+            })?;
         debug!("similarity of best match: {:?}", best_match.0.similarity);
         Some(NameLookupResult {
             name: best_match.0.name.clone(),
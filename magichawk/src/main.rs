@@ -2,14 +2,12 @@
 extern crate rocket;
 extern crate serde_json;
 
-use itertools::Itertools;
-use printpdf::image_crate::DynamicImage;
-use rocket::http::{ContentType, Status};
+use rocket::http::ContentType;
 use rocket::{State, fairing::AdHoc, response::content};
-use std::fs::File;
+use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use magichawk::ScryfallClient;
+use magichawk::{CardSource, ImageSource, ScryfallClient, ScryfallEndpoints};
 
 #[get("/")]
 async fn get_index() -> content::RawHtml<String> {
@@ -17,71 +15,153 @@ async fn get_index() -> content::RawHtml<String> {
     content::RawHtml(index.into())
 }
 
-#[get("/create_pdf?<decklist>&<backside>")]
+#[derive(Debug, FromForm)]
+struct DecklistOptions {
+    backside: magichawk::BacksideMode,
+    meld: Option<magichawk::MeldMode>,
+    digital: Option<bool>,
+    tokens: Option<bool>,
+}
+
+impl DecklistOptions {
+    fn proxy_options(&self) -> magichawk::ProxyOptions {
+        magichawk::ProxyOptions {
+            backside: self.backside,
+            meld: self.meld.unwrap_or(magichawk::MeldMode::Whole),
+            policy: magichawk::PrintingPolicy {
+                allow_digital: self.digital.unwrap_or(false),
+            },
+            tokens: self.tokens.unwrap_or(false),
+        }
+    }
+}
+
+async fn image_lines(
+    card_data: &Mutex<magichawk::CardData>,
+    card_source: &dyn CardSource,
+    decklist: &str,
+    options: &DecklistOptions,
+) -> Result<Vec<magichawk::ImageLine>, magichawk::Error> {
+    // released before the images are downloaded to not block other requests
+    let mut cd = card_data.lock().await;
+    magichawk::proxy_image_lines(decklist, &mut cd, options.proxy_options(), card_source).await
+}
+
+#[get("/create_pdf?<decklist>&<options..>")]
 async fn create_pdf(
-    image_cache: &State<Mutex<magichawk::ScryfallCache>>,
+    image_cache: &State<magichawk::SharedImageCache>,
     card_data: &State<Mutex<magichawk::CardData>>,
-    client: &State<ScryfallClient>,
+    card_source: &State<Box<dyn CardSource>>,
+    image_source: &State<Box<dyn ImageSource>>,
     decklist: String,
-    backside: magichawk::BacksideMode,
-) -> (rocket::http::Status, (rocket::http::ContentType, Vec<u8>)) {
-    let mut cd = card_data.lock().await;
-    let parsed = magichawk::parse_decklist(&decklist, &cd.printings.languages);
-    let cards = magichawk::image_lines_from_decklist(parsed, &mut cd, backside, client).await;
+    options: DecklistOptions,
+) -> Result<(ContentType, Vec<u8>), magichawk::Error> {
+    let cards = image_lines(card_data, card_source.as_ref(), &decklist, &options).await?;
+    let pages = magichawk::render_pages(&cards, image_cache, image_source.as_ref()).await?;
+    let pdf = magichawk::page_images_to_pdf(pages.into_iter(), magichawk::PaperSize::default())
+        .inspect_err(|e| error!("{}", e))?;
+    info!("sending out pdf with size {}", pdf.len());
+    Ok((ContentType::PDF, pdf))
+}
 
-    let mut cache = image_cache.lock().await;
-    for line in cards.iter() {
-        cache.ensure_contains_line(line, client).await;
-    }
+// every request is exported in memory and anyone can send one, so the limits are lower than those
+// of the command line. A page at 600 dpi has about 27 megapixels, the images are resized and
+// encoded one after the other but all of them end up in the zip archive
+const MAX_WEB_DPI: u32 = 600;
+const MAX_WEB_EXPORT_PIXELS: u64 = 500_000_000;
 
-    let mut expanded: Vec<&DynamicImage> = Vec::new();
-    for line in cards.iter() {
-        for (uri, multiplicity) in &line.images {
-            if let Some(image) = cache.get(uri) {
-                for _i in 0..*multiplicity {
-                    expanded.push(image);
-                }
-            }
-        }
+// `None` are more pixels than can be counted
+fn check_export_pixels(pixels: Option<u64>) -> Result<(), magichawk::Error> {
+    if pixels.is_none_or(|pixels| pixels > MAX_WEB_EXPORT_PIXELS) {
+        return Err(magichawk::Error::InvalidOption(format!(
+            "the images would have more than {} megapixels, use a lower dpi or fewer cards",
+            MAX_WEB_EXPORT_PIXELS / 1_000_000
+        )));
     }
+    Ok(())
+}
 
-    if expanded.is_empty() {
-        let message: Vec<u8> = "no card names have been recognized".as_bytes().to_vec();
-        return (Status::BadRequest, (ContentType::Plain, message));
+/// The pages, or with `cards` each card on its own for online print services, as images in a zip
+/// archive. With `print_service` the cards have the size and bleed of the print service and come
+/// with a manifest of how often each is printed.
+#[allow(clippy::too_many_arguments)]
+#[get("/create_zip?<decklist>&<image_format>&<dpi>&<cards>&<print_service>&<options..>")]
+async fn create_zip(
+    image_cache: &State<magichawk::SharedImageCache>,
+    card_data: &State<Mutex<magichawk::CardData>>,
+    card_source: &State<Box<dyn CardSource>>,
+    image_source: &State<Box<dyn ImageSource>>,
+    decklist: String,
+    image_format: Option<magichawk::ImageFileFormat>,
+    dpi: Option<u32>,
+    cards: Option<bool>,
+    print_service: Option<String>,
+    options: DecklistOptions,
+) -> Result<(ContentType, Vec<u8>), magichawk::Error> {
+    if dpi.is_some_and(|dpi| dpi > MAX_WEB_DPI) {
+        return Err(magichawk::Error::InvalidOption(format!(
+            "dpi has to be at most {}",
+            MAX_WEB_DPI
+        )));
     }
-
-    let pdf = magichawk::page_images_to_pdf(
-        expanded
-            .into_iter()
-            .batching(|it| magichawk::images_to_page(it)),
+    // a preset which can't be parsed shouldn't silently fall back to pages
+    let print_service = print_service
+        .map(|preset| preset.parse::<magichawk::PrintServicePreset>())
+        .transpose()
+        .map_err(magichawk::Error::InvalidOption)?;
+    let lines = image_lines(card_data, card_source.as_ref(), &decklist, &options).await?;
+    let image_format = image_format.unwrap_or_default();
+    let cards = cards.unwrap_or(false);
+    // before any image is downloaded or rendered
+    check_export_pixels(if let Some(preset) = print_service {
+        magichawk::export_pixels(
+            magichawk::card_count(&lines),
+            (preset.width, preset.height),
+            None,
+        )
+    } else if cards {
+        magichawk::export_pixels(
+            magichawk::card_count(&lines),
+            (magichawk::IMAGE_WIDTH, magichawk::IMAGE_HEIGHT),
+            dpi,
+        )
+    } else {
+        magichawk::export_pixels(
+            magichawk::page_count(&lines),
+            (magichawk::PAGE_WIDTH, magichawk::PAGE_HEIGHT),
+            dpi,
+        )
+    })?;
+    let files = if let Some(preset) = print_service {
+        let rendered = magichawk::render_cards(&lines, image_cache, image_source.as_ref()).await?;
+        magichawk::print_service_files(&rendered, preset, image_format)?
+    } else if cards {
+        let rendered = magichawk::render_cards(&lines, image_cache, image_source.as_ref()).await?;
+        magichawk::card_files(&rendered, image_format, dpi)?
+    } else {
+        let pages = magichawk::render_pages(&lines, image_cache, image_source.as_ref()).await?;
+        magichawk::page_files(&pages, image_format, dpi)?
+    };
+    let zip = magichawk::files_to_zip(&files).inspect_err(|e| error!("{}", e))?;
+    info!(
+        "sending out zip with {} files and size {}",
+        files.len(),
+        zip.len()
     );
-
-    match pdf {
-        Some(bytes) => {
-            info!("sending out pdf with size {}", bytes.len());
-            (Status::Ok, (ContentType::PDF, bytes))
-        }
-        None => {
-            let message: Vec<u8> = "internal server error (sorry)".as_bytes().to_vec();
-            (Status::InternalServerError, (ContentType::Plain, message))
-        }
-    }
+    Ok((ContentType::ZIP, zip))
 }
 
 #[get("/cache/list")]
-async fn list_cache(state: &State<Mutex<magichawk::ScryfallCache>>) -> content::RawHtml<String> {
-    content::RawHtml(state.lock().await.list())
+async fn list_cache(state: &State<magichawk::SharedImageCache>) -> content::RawHtml<String> {
+    content::RawHtml(state.list().await)
 }
 
 #[get("/cache/purge?<age_seconds>")]
 async fn purge_cache(
-    state: &State<Mutex<magichawk::ScryfallCache>>,
+    state: &State<magichawk::SharedImageCache>,
     age_seconds: Option<i64>,
 ) -> content::RawHtml<String> {
-    state
-        .lock()
-        .await
-        .purge(age_seconds.map(time::Duration::seconds));
+    state.purge(age_seconds.map(time::Duration::seconds)).await;
     list_cache(state).await
 }
 
@@ -117,19 +197,19 @@ async fn card_names_short(
 #[get("/card_names/update")]
 async fn card_names_update(
     card_data_m: &State<Mutex<magichawk::CardData>>,
-    client: &State<ScryfallClient>,
+    card_source: &State<Box<dyn CardSource>>,
 ) -> content::RawHtml<String> {
     let mut card_data = card_data_m.lock().await;
     let n_before = card_data.card_names.names.len();
-    let response = match card_data.update_names(client).await {
-        Some(_) => {
+    let response = match card_data.update_names(card_source.as_ref()).await {
+        Ok(()) => {
             let n_after = card_data.card_names.names.len();
             format!(
                 "card names updated, {} names before, {} names after",
                 n_before, n_after
             )
         }
-        None => "couldn't update card names".to_string(),
+        Err(e) => format!("couldn't update card names: {}", e),
     };
     content::RawHtml(response)
 }
@@ -166,9 +246,104 @@ async fn card_data_full(
     content::RawJson(serialized)
 }
 
+/// Where card names and printings come from. Test fixtures are served by the bulk source, which
+/// reads any json array of scryfall objects.
+#[derive(Debug, Default, rocket::serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CardSourceKind {
+    #[default]
+    Scryfall,
+    /// the bulk data file or directory of files in `bulk_data`
+    Bulk,
+}
+
+/// Where images come from. Test fixtures are served by the directory source.
+#[derive(Debug, Default, rocket::serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ImageSourceKind {
+    #[default]
+    Scryfall,
+    /// the images previously downloaded into `image_dir`
+    Directory,
+}
+
 #[derive(Debug, rocket::serde::Deserialize)]
 struct AppConfig {
     card_data: Option<String>,
+    image_cache_dir: Option<String>,
+    image_cache_dir_max_bytes: Option<u64>,
+    image_cache_max_bytes: Option<usize>,
+    image_cache_max_entries: Option<usize>,
+    scryfall_interval_ms: Option<u64>,
+    scryfall_api_url: Option<String>,
+    scryfall_image_url: Option<String>,
+    /// never call scryfall, cards come from `bulk_data` and images from `image_dir`
+    #[serde(default)]
+    offline: bool,
+    #[serde(default)]
+    card_source: CardSourceKind,
+    bulk_data: Option<String>,
+    #[serde(default)]
+    image_source: ImageSourceKind,
+    image_dir: Option<String>,
+}
+
+fn scryfall_client(config: &AppConfig) -> ScryfallClient {
+    let endpoints = ScryfallEndpoints::new(
+        config
+            .scryfall_api_url
+            .as_deref()
+            .unwrap_or(magichawk::SCRYFALL_API_BASE),
+        config
+            .scryfall_image_url
+            .as_deref()
+            .unwrap_or(magichawk::SCRYFALL_IMAGE_BASE),
+    );
+    ScryfallClient::new().with_endpoints(endpoints)
+}
+
+fn bulk_card_source(config: &AppConfig) -> Result<Box<dyn CardSource>, String> {
+    let path = config
+        .bulk_data
+        .as_ref()
+        .ok_or("bulk_data is needed for the bulk card source")?;
+    match magichawk::BulkDataSource::from_path(path) {
+        Ok(source) => Ok(Box::new(source)),
+        Err(e) => Err(format!("couldn't read bulk data: {}", e)),
+    }
+}
+
+fn directory_image_source(config: &AppConfig) -> Result<Box<dyn ImageSource>, String> {
+    let dir = config
+        .image_dir
+        .as_ref()
+        .ok_or("image_dir is needed for the directory image source")?;
+    match magichawk::ImageStore::new(dir) {
+        Ok(store) => Ok(Box::new(store)),
+        Err(e) => Err(format!("couldn't open image directory: {}", e)),
+    }
+}
+
+type Sources = (Box<dyn CardSource>, Box<dyn ImageSource>);
+
+// a local source which can't be used stops the launch instead of falling back to scryfall,
+// whoever configured it doesn't expect any calls to scryfall
+fn create_sources(config: &AppConfig) -> Result<Sources, String> {
+    if config.offline {
+        info!("offline mode, scryfall isn't called");
+        return Ok((bulk_card_source(config)?, directory_image_source(config)?));
+    }
+    // one client for both, so they share the circuit breaker and retries
+    let client = Arc::new(scryfall_client(config));
+    let card_source = match config.card_source {
+        CardSourceKind::Scryfall => Box::new(client.clone()),
+        CardSourceKind::Bulk => bulk_card_source(config)?,
+    };
+    let image_source = match config.image_source {
+        ImageSourceKind::Scryfall => Box::new(client),
+        ImageSourceKind::Directory => directory_image_source(config)?,
+    };
+    Ok((card_source, image_source))
 }
 
 async fn trigger_local_call(name: String, url: String, interval: std::time::Duration) {
@@ -227,36 +402,99 @@ fn rocket() -> _ {
                 })
             },
         ))
-        .attach(AdHoc::on_ignite("create reqwest client", |rocket| async {
-            rocket.manage(ScryfallClient::new())
-        }))
-        .attach(AdHoc::on_ignite(
-            "load card data from file",
+        .attach(AdHoc::try_on_ignite(
+            "create data sources",
             |rocket| async {
-                let client = rocket
-                    .state::<ScryfallClient>()
-                    .expect("we should always be able to get a scryfall client");
-                let card_data_from_file: Option<magichawk::CardData> = async {
-                    let file_name = rocket.state::<AppConfig>().unwrap().card_data.clone();
-                    let file_handle = File::open(file_name?).ok()?;
-                    let deserialized: magichawk::CardPrintings =
-                        serde_json::from_reader(file_handle)
-                            .ok()
-                            .unwrap_or_else(magichawk::get_minimal_card_printings);
-
-                    magichawk::CardData::from_printings(deserialized, client).await
+                let config = rocket.state::<AppConfig>().unwrap();
+                if let Some(interval) = config.scryfall_interval_ms {
+                    // shared with the blocking calls, so set it on the process-wide limiter
+                    magichawk::scryfall_rate_limiter()
+                        .set_interval(std::time::Duration::from_millis(interval));
+                }
+                match create_sources(config) {
+                    Ok((card_source, image_source)) => {
+                        Ok(rocket.manage(card_source).manage(image_source))
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                        Err(rocket)
+                    }
                 }
-                .await;
-                let card_data = card_data_from_file.unwrap_or(
-                    magichawk::CardData::from_client(client).await.expect(
-                        "we should always be able to create card data from the scryfall client",
-                    ),
-                );
-                rocket.manage(Mutex::new(card_data))
+            },
+        ))
+        .attach(AdHoc::try_on_ignite(
+            "load card data from file",
+            |rocket| async {
+                let source = rocket
+                    .state::<Box<dyn CardSource>>()
+                    .expect("the card source is created before the card data")
+                    .as_ref();
+                let file_name = rocket.state::<AppConfig>().unwrap().card_data.clone();
+                // json or binary, see create_card_data. Without a file the cards are looked up
+                // when asked for, but a file which is there and can't be read is a mistake
+                let printings = match file_name.map(std::fs::read) {
+                    Some(Ok(bytes)) => match magichawk::CardPrintings::from_bytes(&bytes) {
+                        Ok(printings) => printings,
+                        Err(e) => {
+                            error!("couldn't read card data: {}", e);
+                            return Err(rocket);
+                        }
+                    },
+                    Some(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                        info!("no card data file, starting without card data");
+                        magichawk::CardPrintings::new()
+                    }
+                    Some(Err(e)) => {
+                        error!("couldn't open card data: {}", e);
+                        return Err(rocket);
+                    }
+                    None => magichawk::CardPrintings::new(),
+                };
+                // printings of further cards are looked up in the card source when asked for
+                let card_names = match source.card_names().await {
+                    Ok(card_names) => card_names,
+                    Err(e) => {
+                        error!(
+                            "couldn't get card names, using the names in the card data: {}",
+                            e
+                        );
+                        magichawk::ScryfallCardNames::from_printings(&printings)
+                    }
+                };
+                let card_data = magichawk::CardData::with_card_names(printings, card_names);
+                Ok(rocket.manage(Mutex::new(card_data)))
             },
         ))
         .attach(AdHoc::on_ignite("create image cache", |rocket| async {
-            rocket.manage(Mutex::new(magichawk::ScryfallCache::new()))
+            let config = rocket.state::<AppConfig>().unwrap();
+            let mut cache = match config
+                .image_cache_dir
+                .clone()
+                .map(magichawk::ImageStore::new)
+            {
+                Some(Ok(store)) => {
+                    let store = store.with_max_bytes(
+                        config
+                            .image_cache_dir_max_bytes
+                            .unwrap_or(magichawk::ImageStore::DEFAULT_MAX_BYTES),
+                    );
+                    info!("storing images in {:?}", store.dir());
+                    magichawk::ScryfallCache::with_store(store)
+                }
+                Some(Err(e)) => {
+                    error!("couldn't create image store, caching in memory only: {}", e);
+                    magichawk::ScryfallCache::new()
+                }
+                None => magichawk::ScryfallCache::new(),
+            };
+            let default_budget = magichawk::CacheBudget::default();
+            cache.set_budget(magichawk::CacheBudget {
+                max_bytes: config.image_cache_max_bytes.or(default_budget.max_bytes),
+                max_entries: config
+                    .image_cache_max_entries
+                    .or(default_budget.max_entries),
+            });
+            rocket.manage(magichawk::SharedImageCache::new(cache))
         }))
         // .mount("/", FileServer::from(Path::new("static")))
         .mount("/", routes![get_index])
@@ -267,6 +505,7 @@ fn rocket() -> _ {
         .mount("/", routes![card_data_short])
         .mount("/", routes![lookup])
         .mount("/", routes![create_pdf])
+        .mount("/", routes![create_zip])
         .mount("/", routes![list_cache])
         .mount("/", routes![purge_cache])
 }
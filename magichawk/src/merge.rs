@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::bulk::ScryfallObject;
+use crate::scryfall::{CardPrintings, MinimalScryfallObject, is_card, is_extra};
+
+/// The printings which differ after a merge, the new versions of added and changed printings and
+/// the old versions of removed ones.
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub added: Vec<MinimalScryfallObject>,
+    pub changed: Vec<MinimalScryfallObject>,
+    pub removed: Vec<MinimalScryfallObject>,
+    pub unchanged: usize,
+    /// objects which are neither cards nor extras or couldn't be converted
+    pub skipped: usize,
+}
+
+impl MergeReport {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl fmt::Display for MergeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} changed, {} removed and {} unchanged printings, {} objects skipped",
+            self.added.len(),
+            self.changed.len(),
+            self.removed.len(),
+            self.unchanged,
+            self.skipped
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Card,
+    Extra,
+}
+
+// printings in card data written before the scryfall id was stored can't be matched by it
+pub(crate) fn printing_key(printing: &MinimalScryfallObject) -> String {
+    match &printing.id {
+        Some(id) => id.clone(),
+        None => format!(
+            "{}|{}|{}|{}",
+            printing.name,
+            printing.set,
+            printing.collector_number.as_deref().unwrap_or_default(),
+            printing.language
+        ),
+    }
+}
+
+/// Merges newer scryfall objects, e.g. from a newer bulk data file or a search for recently
+/// released cards, into existing card data. Printings are matched by their scryfall id so that
+/// nothing is duplicated, changed printings are replaced where they are.
+pub struct CardDataMerge {
+    printings: CardPrintings,
+    // where the printing with a key is stored
+    index: HashMap<String, (Kind, String)>,
+    seen: HashSet<String>,
+    report: MergeReport,
+}
+
+impl CardDataMerge {
+    pub fn new(printings: CardPrintings) -> CardDataMerge {
+        let mut index = HashMap::new();
+        for (kind, by_name) in [
+            (Kind::Card, &printings.printings),
+            (Kind::Extra, &printings.extras),
+        ] {
+            for (name, list) in by_name {
+                for printing in list {
+                    index.insert(printing_key(printing), (kind, name.clone()));
+                }
+            }
+        }
+        CardDataMerge {
+            printings,
+            index,
+            seen: HashSet::new(),
+            report: MergeReport::default(),
+        }
+    }
+
+    fn by_name(&mut self, kind: Kind) -> &mut HashMap<String, Vec<MinimalScryfallObject>> {
+        match kind {
+            Kind::Card => &mut self.printings.printings,
+            Kind::Extra => &mut self.printings.extras,
+        }
+    }
+
+    fn take(&mut self, kind: Kind, name: &str, key: &str) -> Option<MinimalScryfallObject> {
+        let by_name = self.by_name(kind);
+        let list = by_name.get_mut(name)?;
+        let position = list
+            .iter()
+            .position(|printing| printing_key(printing) == key)?;
+        let printing = list.remove(position);
+        if list.is_empty() {
+            by_name.remove(name);
+        }
+        Some(printing)
+    }
+
+    pub fn insert(&mut self, object: &ScryfallObject) {
+        let kind = if is_card(object) {
+            Kind::Card
+        } else if is_extra(object) {
+            Kind::Extra
+        } else {
+            self.report.skipped += 1;
+            return;
+        };
+        let Some(printing) = MinimalScryfallObject::from_dict(object) else {
+            self.report.skipped += 1;
+            return;
+        };
+        let key = printing_key(&printing);
+        // the same printing can appear twice, e.g. in overlapping searches
+        let first_time = self.seen.insert(key.clone());
+        if kind == Kind::Card {
+            self.printings.languages.insert(printing.language.clone());
+        }
+        match self.index.get(&key).cloned() {
+            Some((old_kind, old_name)) if old_kind == kind && old_name == printing.name => {
+                let by_name = match kind {
+                    Kind::Card => &mut self.printings.printings,
+                    Kind::Extra => &mut self.printings.extras,
+                };
+                let old = by_name
+                    .get_mut(&old_name)
+                    .and_then(|list| list.iter_mut().find(|old| printing_key(old) == key))
+                    .expect("the index points to stored printings");
+                if *old == printing {
+                    if first_time {
+                        self.report.unchanged += 1;
+                    }
+                } else {
+                    if first_time {
+                        self.report.changed.push(printing.clone());
+                    }
+                    *old = printing;
+                }
+            }
+            old_place => {
+                match old_place {
+                    Some((old_kind, old_name)) => {
+                        self.take(old_kind, &old_name, &key);
+                        if first_time {
+                            self.report.changed.push(printing.clone());
+                        }
+                    }
+                    None => self.report.added.push(printing.clone()),
+                }
+                self.index.insert(key, (kind, printing.name.clone()));
+                self.by_name(kind)
+                    .entry(printing.name.clone())
+                    .or_default()
+                    .push(printing);
+            }
+        }
+    }
+
+    /// The merged card data, when the merged objects were complete like a bulk data file the
+    /// printings which weren't among them are removed.
+    pub fn finish(mut self, remove_missing: bool) -> (CardPrintings, MergeReport) {
+        if remove_missing {
+            let missing: Vec<(String, (Kind, String))> = self
+                .index
+                .iter()
+                .filter(|(key, _)| !self.seen.contains(*key))
+                .map(|(key, place)| (key.clone(), place.clone()))
+                .collect();
+            for (key, (kind, name)) in missing {
+                if let Some(printing) = self.take(kind, &name, &key) {
+                    self.report.removed.push(printing);
+                }
+            }
+            self.report
+                .removed
+                .sort_by(|a, b| (&a.name, &a.set).cmp(&(&b.name, &b.set)));
+        }
+        (self.printings, self.report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meld_objects() -> Vec<ScryfallObject> {
+        serde_json::from_str(include_str!("../test_input/default-cards_meld.json")).unwrap()
+    }
+
+    fn merge(
+        printings: CardPrintings,
+        objects: &[ScryfallObject],
+        remove_missing: bool,
+    ) -> (CardPrintings, MergeReport) {
+        let mut merge = CardDataMerge::new(printings);
+        for object in objects {
+            merge.insert(object);
+        }
+        merge.finish(remove_missing)
+    }
+
+    fn count(printings: &CardPrintings) -> usize {
+        printings.printings.values().map(|list| list.len()).sum()
+    }
+
+    #[test]
+    fn added_changed_removed() {
+        let objects = meld_objects();
+        let (printings, report) = merge(CardPrintings::new(), &objects[..6], false);
+        // the art series card is skipped
+        assert_eq!((report.added.len(), report.skipped), (5, 1));
+        assert_eq!(count(&printings), 5);
+
+        let mut newer = objects.clone();
+        newer.remove(1);
+        newer[1].insert("collector_number".to_string(), "999".into());
+        let (printings, report) = merge(printings, &newer, true);
+        assert_eq!(report.added.len(), 4);
+        assert_eq!(report.changed.len(), 1);
+        assert_eq!(report.changed[0].collector_number.as_deref(), Some("999"));
+        assert_eq!(report.removed.len(), 1);
+        assert_eq!(report.removed[0].set, "pbro");
+        assert_eq!(report.unchanged, 3);
+        assert_eq!(count(&printings), 8);
+    }
+
+    #[test]
+    fn merging_twice_changes_nothing() {
+        let objects = meld_objects();
+        let (printings, _) = merge(CardPrintings::new(), &objects, true);
+        let before = count(&printings);
+        // a search overlapping the bulk data doesn't duplicate printings
+        let (printings, report) = merge(printings, &[objects.clone(), objects].concat(), false);
+        assert!(report.is_empty());
+        assert_eq!(report.unchanged, before);
+        assert_eq!(count(&printings), before);
+    }
+}
@@ -1,5 +1,9 @@
 use printpdf::image_crate::DynamicImage;
 use printpdf::{Image, ImageTransform, Mm, PdfDocument};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
 
 use crate::IMAGE_HEIGHT;
 use crate::IMAGE_WIDTH;
@@ -7,23 +11,67 @@ use crate::IMAGE_WIDTH;
 use crate::IMAGE_HEIGHT_CM;
 use crate::IMAGE_WIDTH_CM;
 
-const A4_WIDTH: Mm = Mm(210.0);
-const A4_HEIGHT: Mm = Mm(297.0);
-
-const INCH_DIV_CM: f32 = 2.54;
+pub(crate) const INCH_DIV_CM: f32 = 2.54;
 const DPI: f32 = 300.0;
 const DPCM: f32 = DPI / INCH_DIV_CM;
 
-pub fn page_images_to_pdf<I>(it: I) -> Option<Vec<u8>>
+/// The paper the pdf is printed on, the 3x3 cards of a page are centered on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaperSize {
+    #[default]
+    A4,
+    /// US letter, 8.5 x 11 inches
+    Letter,
+}
+
+impl PaperSize {
+    fn width(&self) -> Mm {
+        match self {
+            PaperSize::A4 => Mm(210.0),
+            PaperSize::Letter => Mm(215.9),
+        }
+    }
+
+    fn height(&self) -> Mm {
+        match self {
+            PaperSize::A4 => Mm(297.0),
+            PaperSize::Letter => Mm(279.4),
+        }
+    }
+}
+
+impl FromStr for PaperSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "a4" => Ok(PaperSize::A4),
+            "letter" => Ok(PaperSize::Letter),
+            _ => Err(format!("unknown paper size {}, expected a4 or letter", s)),
+        }
+    }
+}
+
+impl fmt::Display for PaperSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaperSize::A4 => write!(f, "a4"),
+            PaperSize::Letter => write!(f, "letter"),
+        }
+    }
+}
+
+pub fn page_images_to_pdf<I>(it: I, paper: PaperSize) -> Result<Vec<u8>>
 where
     I: Iterator<Item = DynamicImage>,
 {
-    let (doc, page1, layer1) = PdfDocument::new("Proxies", A4_WIDTH, A4_HEIGHT, "Layer 1");
+    let (width, height) = (paper.width(), paper.height());
+    let (doc, page1, layer1) = PdfDocument::new("Proxies", width, height, "Layer 1");
 
     let transform = ImageTransform {
         dpi: Some(DPI),
-        translate_x: Some((A4_WIDTH - Mm(3.0 * IMAGE_WIDTH_CM * 10.0)) / 2.0),
-        translate_y: Some((A4_HEIGHT - Mm(3.0 * IMAGE_HEIGHT_CM * 10.0)) / 2.0),
+        translate_x: Some((width - Mm(3.0 * IMAGE_WIDTH_CM * 10.0)) / 2.0),
+        translate_y: Some((height - Mm(3.0 * IMAGE_HEIGHT_CM * 10.0)) / 2.0),
         scale_x: Some(IMAGE_WIDTH_CM / (IMAGE_WIDTH as f32) * DPCM),
         scale_y: Some(IMAGE_HEIGHT_CM / (IMAGE_HEIGHT as f32) * DPCM),
         rotate: None,
@@ -31,7 +79,7 @@ where
 
     for (i, im) in it.enumerate() {
         if i > 0 {
-            let (added_page, added_layer) = doc.add_page(A4_WIDTH, A4_HEIGHT, "Layer 1");
+            let (added_page, added_layer) = doc.add_page(width, height, "Layer 1");
             let current_layer = doc.get_page(added_page).get_layer(added_layer);
             Image::from_dynamic_image(&im).add_to_layer(current_layer.clone(), transform);
         } else {
@@ -39,5 +87,23 @@ where
             Image::from_dynamic_image(&im).add_to_layer(current_layer.clone(), transform);
         }
     }
-    doc.save_to_bytes().ok()
+    doc.save_to_bytes().map_err(|e| Error::Pdf(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paper_sizes() {
+        assert_eq!("A4".parse(), Ok(PaperSize::A4));
+        assert_eq!("letter".parse(), Ok(PaperSize::Letter));
+        assert!("legal".parse::<PaperSize>().is_err());
+        assert_eq!(PaperSize::Letter.to_string(), "letter");
+        // the cards have to fit on every paper size
+        for paper in [PaperSize::A4, PaperSize::Letter] {
+            assert!(paper.width().0 > 30.0 * IMAGE_WIDTH_CM);
+            assert!(paper.height().0 > 30.0 * IMAGE_HEIGHT_CM);
+        }
+    }
 }
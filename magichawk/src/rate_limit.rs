@@ -0,0 +1,138 @@
+use log::debug;
+use tokio::time::{Duration, Instant};
+
+struct LimiterState {
+    interval: Duration,
+    // theoretical arrival time of the next call if calls were perfectly spaced
+    next: Instant,
+}
+
+/// Spaces out calls by a minimum interval, allowing up to `burst` calls at once after a quiet
+/// period. Each caller reserves its own slot, so concurrent callers queue up instead of all
+/// waiting for the same one, and async and blocking callers can share a limiter.
+pub struct RateLimiter {
+    burst: u32,
+    state: std::sync::Mutex<LimiterState>,
+}
+
+impl RateLimiter {
+    pub fn new(interval: Duration, burst: u32) -> RateLimiter {
+        RateLimiter {
+            burst: burst.max(1),
+            state: std::sync::Mutex::new(LimiterState {
+                interval,
+                next: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.state.lock().unwrap().interval
+    }
+
+    pub fn set_interval(&self, interval: Duration) {
+        self.state.lock().unwrap().interval = interval;
+    }
+
+    /// Reserves the next free slot and returns the time at which it starts.
+    pub fn reserve(&self) -> Instant {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let next = state.next.max(now);
+        let slot = next
+            .checked_sub(state.interval * (self.burst - 1))
+            .map_or(now, |earliest| earliest.max(now));
+        state.next = next + state.interval;
+        slot
+    }
+
+    pub async fn wait(&self) {
+        let slot = self.reserve();
+        debug!(
+            "waiting {:?} for rate limit",
+            slot.saturating_duration_since(Instant::now())
+        );
+        tokio::time::sleep_until(slot).await;
+    }
+
+    pub fn wait_blocking(&self) {
+        let slot = self.reserve();
+        let now = Instant::now();
+        if slot > now {
+            std::thread::sleep(slot - now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    async fn call_times(limiter: Arc<RateLimiter>, n: usize) -> Vec<Duration> {
+        let start = Instant::now();
+        let calls: Vec<_> = (0..n)
+            .map(|_| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move {
+                    limiter.wait().await;
+                    Instant::now() - start
+                })
+            })
+            .collect();
+        let mut times = Vec::new();
+        for call in calls {
+            times.push(call.await.unwrap());
+        }
+        times.sort();
+        times
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_calls_are_spaced() {
+        let limiter = Arc::new(RateLimiter::new(INTERVAL, 1));
+        let times = call_times(limiter, 5).await;
+        let expected: Vec<Duration> = (0..5).map(|i| INTERVAL * i).collect();
+        assert_eq!(times, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_limiter_does_not_wait() {
+        let limiter = Arc::new(RateLimiter::new(INTERVAL, 1));
+        limiter.wait().await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        let times = call_times(limiter, 2).await;
+        assert_eq!(times, [Duration::ZERO, INTERVAL]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst() {
+        let limiter = Arc::new(RateLimiter::new(INTERVAL, 3));
+        let times = call_times(limiter, 5).await;
+        assert_eq!(
+            times,
+            [
+                Duration::ZERO,
+                Duration::ZERO,
+                Duration::ZERO,
+                INTERVAL,
+                INTERVAL * 2
+            ]
+        );
+    }
+
+    #[test]
+    fn blocking_callers_share_slots() {
+        let limiter = RateLimiter::new(Duration::from_secs(3600), 1);
+        let first = limiter.reserve();
+        let second = limiter.reserve();
+        assert_eq!(second - first, Duration::from_secs(3600));
+        // the first slot is free, so this doesn't sleep
+        let limiter = RateLimiter::new(Duration::from_secs(3600), 1);
+        limiter.wait_blocking();
+        limiter.set_interval(INTERVAL);
+        assert_eq!(limiter.interval(), INTERVAL);
+    }
+}
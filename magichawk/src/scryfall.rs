@@ -1,11 +1,13 @@
 use log::{error, info};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::io::Read;
 use time::OffsetDateTime;
 
-use crate::scryfall_client::{ScryfallClient, blocking_call};
+use crate::error::{Error, Result};
+use crate::scryfall_client::{ClientError, ScryfallClient, ScryfallEndpoints, blocking_call};
 
-const SCRYFALL_CARD_NAMES: &str = "https://api.scryfall.com/catalog/card-names";
+const SCRYFALL_CARD_NAMES: &str = "/catalog/card-names";
 
 // https://scryfall.com/docs/api/languages 2022-12-31
 pub fn get_minimal_scryfall_languages() -> HashSet<String> {
@@ -18,13 +20,6 @@ pub fn get_minimal_scryfall_languages() -> HashSet<String> {
     )
 }
 
-pub fn get_minimal_card_printings() -> CardPrintings {
-    let serialized = include_str!("../assets/card_data.json");
-    let printings: CardPrintings = serde_json::from_str(serialized)
-        .expect("should always be able to parse card data included in binary");
-    printings
-}
-
 fn encode_card_name(name: &str) -> String {
     name.replace(' ', "+").replace("//", "")
 }
@@ -40,30 +35,49 @@ pub struct ScryfallCardNames {
 }
 
 impl ScryfallCardNames {
-    pub async fn from_api_call(client: &ScryfallClient) -> Option<ScryfallCardNames> {
+    /// Card names which don't come from the catalog of the scryfall API, e.g. from bulk data.
+    pub fn from_names(names: Vec<String>) -> ScryfallCardNames {
+        ScryfallCardNames {
+            object: "catalog".to_string(),
+            uri: String::new(),
+            total_values: names.len() as i32,
+            date: Some(OffsetDateTime::now_utc()),
+            names,
+        }
+    }
+
+    /// The names of the cards which have printings in the card data, without extras.
+    pub fn from_printings(printings: &CardPrintings) -> ScryfallCardNames {
+        let mut names: Vec<String> = printings
+            .printings
+            .keys()
+            .map(|name| name.to_lowercase())
+            .collect();
+        names.sort();
+        ScryfallCardNames::from_names(names)
+    }
+
+    pub async fn from_api_call(client: &ScryfallClient) -> Result<ScryfallCardNames> {
         let mut card_names: ScryfallCardNames = client
-            .call(SCRYFALL_CARD_NAMES)
-            .await
-            .ok()?
+            .call(&client.endpoints().api(SCRYFALL_CARD_NAMES))
+            .await?
             .json::<ScryfallCardNames>()
-            .await
-            .ok()?;
+            .await?;
         card_names.date = Some(OffsetDateTime::now_utc());
         for name in card_names.names.iter_mut() {
             *name = name.to_lowercase();
         }
-        Some(card_names)
+        Ok(card_names)
     }
 
-    pub fn from_api_call_blocking() -> Option<ScryfallCardNames> {
-        let card_names = blocking_call(SCRYFALL_CARD_NAMES).ok()?;
-        // print!("card_names: {:?}", card_names.text());
-        let mut card_names_json = card_names.json::<ScryfallCardNames>().unwrap();
+    pub fn from_api_call_blocking(endpoints: &ScryfallEndpoints) -> Result<ScryfallCardNames> {
+        let card_names = blocking_call(&endpoints.api(SCRYFALL_CARD_NAMES))?;
+        let mut card_names_json = card_names.json::<ScryfallCardNames>()?;
         card_names_json.date = Some(OffsetDateTime::now_utc());
         for name in card_names_json.names.iter_mut() {
             *name = name.to_lowercase();
         }
-        Some(card_names_json)
+        Ok(card_names_json)
     }
 }
 
@@ -76,48 +90,101 @@ pub struct ScryfallSearchAnswer {
     pub data: Vec<serde_json::Map<String, serde_json::Value>>,
 }
 
-// the uri of the border crop for a meld result is not part of the scryfall object
+/// A physical side of a printing, split and adventure cards have a single side showing all of
+/// their halves while transforming, modal and reversible cards have one side per face.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CardFace {
+    pub name: String,
+    pub border_crop: String,
+}
+
+/// A token, emblem or dungeon which a card creates, from the all_parts of the scryfall object.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RelatedCard {
+    pub id: String,
+    pub name: String,
+}
+
+// the uri of the border crop for a meld result is not part of the scryfall object,
+// the printing metadata has defaults so that card data files written before it was added still load
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct MinimalScryfallObject {
     pub name: String,
     pub set: String,
     pub language: String,
-    pub border_crop: String,
-    pub border_crop_back: Option<String>,
+    pub faces: Vec<CardFace>,
     pub meld_result: Option<String>,
+    #[serde(default)]
+    pub collector_number: Option<String>,
+    #[serde(default)]
+    pub released_at: Option<String>,
+    #[serde(default)]
+    pub frame: Option<String>,
+    #[serde(default)]
+    pub border_color: Option<String>,
+    #[serde(default)]
+    pub full_art: bool,
+    #[serde(default)]
+    pub promo: bool,
+    #[serde(default)]
+    pub digital: bool,
+    #[serde(default)]
+    pub layout: Option<String>,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub oracle_id: Option<String>,
+    #[serde(default)]
+    pub tokens: Vec<RelatedCard>,
+}
+
+fn get_string(d: &serde_json::Map<String, serde_json::Value>, key: &str) -> Option<String> {
+    d.get(key)?.as_str().map(String::from)
+}
+
+fn get_bool(d: &serde_json::Map<String, serde_json::Value>, key: &str) -> bool {
+    d.get(key).and_then(|v| v.as_bool()).unwrap_or(false)
 }
 
 impl MinimalScryfallObject {
+    pub fn front(&self) -> Option<&CardFace> {
+        self.faces.first()
+    }
+
     pub fn from_dict(
         d: &serde_json::Map<String, serde_json::Value>,
     ) -> Option<MinimalScryfallObject> {
-        let name: String = d["name"].as_str()?.to_string().to_lowercase();
+        let full_name: String = d["name"].as_str()?.to_string().to_lowercase();
         let set = d["set"].as_str()?.to_string().to_lowercase();
         let language = d["lang"].as_str()?.to_string().to_lowercase();
 
-        let (border_crop, border_crop_back) = {
-            if d.contains_key("image_uris") {
-                let front = d["image_uris"]["border_crop"].as_str()?.to_string();
-                (front, None)
-            } else if d.contains_key("card_faces") {
-                let card_faces = d["card_faces"].as_array()?;
-                if card_faces.len() != 2 {
-                    return None;
-                } else {
-                    (
-                        card_faces[0]["image_uris"]["border_crop"]
-                            .as_str()?
-                            .to_string(),
-                        Some(
-                            card_faces[1]["image_uris"]["border_crop"]
-                                .as_str()?
-                                .to_string(),
-                        ),
-                    )
-                }
-            } else {
-                return None;
-            }
+        let faces = if d.contains_key("image_uris") {
+            vec![CardFace {
+                name: full_name.clone(),
+                border_crop: d["image_uris"]["border_crop"].as_str()?.to_string(),
+            }]
+        } else if d.contains_key("card_faces") {
+            d["card_faces"]
+                .as_array()?
+                .iter()
+                .map(|face| {
+                    Some(CardFace {
+                        name: face["name"].as_str()?.to_lowercase(),
+                        border_crop: face["image_uris"]["border_crop"].as_str()?.to_string(),
+                    })
+                })
+                .collect::<Option<Vec<CardFace>>>()?
+        } else {
+            return None;
+        };
+        if faces.is_empty() {
+            return None;
+        }
+        // reversible cards are named "x // x" but are just another printing of x
+        let name = if faces.len() > 1 && faces.iter().all(|face| face.name == faces[0].name) {
+            faces[0].name.clone()
+        } else {
+            full_name
         };
         let meld_result = if d["layout"] == "meld" {
             let all_parts = &d["all_parts"].as_array()?;
@@ -134,21 +201,59 @@ impl MinimalScryfallObject {
         } else {
             None
         };
+        let id = get_string(d, "id");
+        let tokens = d
+            .get("all_parts")
+            .and_then(|all_parts| all_parts.as_array())
+            .map(|all_parts| {
+                all_parts
+                    .iter()
+                    .filter(|part| part["component"] == "token")
+                    .filter_map(|part| {
+                        Some(RelatedCard {
+                            id: part["id"].as_str()?.to_string(),
+                            name: part["name"].as_str()?.to_lowercase(),
+                        })
+                    })
+                    .filter(|related| Some(&related.id) != id.as_ref())
+                    .collect()
+            })
+            .unwrap_or_default();
+        // reversible cards only have an oracle id on their faces
+        let oracle_id = get_string(d, "oracle_id").or_else(|| {
+            d.get("card_faces")?.as_array()?.first()?["oracle_id"]
+                .as_str()
+                .map(String::from)
+        });
         Some(MinimalScryfallObject {
             name,
             set,
             language,
-            border_crop,
-            border_crop_back,
+            faces,
             meld_result,
+            collector_number: get_string(d, "collector_number"),
+            released_at: get_string(d, "released_at"),
+            frame: get_string(d, "frame"),
+            border_color: get_string(d, "border_color"),
+            full_art: get_bool(d, "full_art"),
+            promo: get_bool(d, "promo"),
+            digital: get_bool(d, "digital"),
+            layout: get_string(d, "layout"),
+            id,
+            oracle_id,
+            tokens,
         })
     }
 }
 
+/// Printings of cards by name, tokens, emblems, dungeons and other helper cards are kept apart
+/// in extras so that they are only used when asked for.
 #[derive(Serialize, Deserialize)]
 pub struct CardPrintings {
     pub printings: HashMap<String, Vec<MinimalScryfallObject>>,
     pub languages: HashSet<String>,
+    #[serde(default)]
+    pub extras: HashMap<String, Vec<MinimalScryfallObject>>,
 }
 
 impl CardPrintings {
@@ -156,8 +261,55 @@ impl CardPrintings {
         CardPrintings {
             printings: HashMap::new(),
             languages: get_minimal_scryfall_languages(),
+            extras: HashMap::new(),
         }
     }
+
+    pub fn find_extra_by_id(&self, id: &str) -> Option<&MinimalScryfallObject> {
+        self.extras
+            .values()
+            .flatten()
+            .find(|extra| extra.id.as_deref() == Some(id))
+    }
+
+    /// Read a card data file, files written before printings had a list of faces are converted.
+    pub fn from_reader<R: Read>(reader: R) -> serde_json::Result<CardPrintings> {
+        let mut value: serde_json::Value = serde_json::from_reader(reader)?;
+        if let Some(printings) = value
+            .get_mut("printings")
+            .and_then(|printings| printings.as_object_mut())
+        {
+            for object in printings
+                .values_mut()
+                .filter_map(|objects| objects.as_array_mut())
+                .flatten()
+                .filter_map(|object| object.as_object_mut())
+            {
+                upgrade_legacy_faces(object);
+            }
+        }
+        serde_json::from_value(value)
+    }
+}
+
+fn upgrade_legacy_faces(object: &mut serde_json::Map<String, serde_json::Value>) {
+    if object.contains_key("faces") {
+        return;
+    }
+    let name = object
+        .get("name")
+        .cloned()
+        .unwrap_or(serde_json::Value::Null);
+    let faces: Vec<serde_json::Value> = ["border_crop", "border_crop_back"]
+        .iter()
+        .filter_map(|key| match object.remove(*key) {
+            Some(serde_json::Value::String(uri)) => {
+                Some(serde_json::json!({"name": name, "border_crop": uri}))
+            }
+            _ => None,
+        })
+        .collect();
+    object.insert("faces".to_string(), serde_json::Value::Array(faces));
 }
 
 impl Default for CardPrintings {
@@ -193,33 +345,206 @@ pub fn insert_scryfall_object(
     printings.languages.insert(language);
 }
 
-pub async fn query_scryfall_by_name(
+// what from_dict, is_extra and is_card read, the rest of a scryfall object is dropped by reduce
+const MINIMAL_KEYS: [&str; 18] = [
+    "id",
+    "oracle_id",
+    "name",
+    "lang",
+    "set",
+    "set_type",
+    "layout",
+    "type_line",
+    "image_uris",
+    "card_faces",
+    "all_parts",
+    "collector_number",
+    "released_at",
+    "frame",
+    "border_color",
+    "full_art",
+    "promo",
+    "digital",
+];
+const MINIMAL_FACE_KEYS: [&str; 3] = ["name", "image_uris", "oracle_id"];
+const MINIMAL_PART_KEYS: [&str; 3] = ["id", "name", "component"];
+
+fn retain_keys(value: &mut serde_json::Value, keys: &[&str]) {
+    if let Some(object) = value.as_object_mut() {
+        object.retain(|key, _| keys.contains(&key.as_str()));
+    }
+}
+
+fn retain_keys_of_all(value: Option<&mut serde_json::Value>, keys: &[&str]) {
+    if let Some(values) = value.and_then(|value| value.as_array_mut()) {
+        values.iter_mut().for_each(|value| retain_keys(value, keys));
+    }
+}
+
+/// Drops everything from a scryfall object which isn't needed to build card data from it, so
+/// that all objects of a bulk data file can be kept in memory.
+pub fn reduce(
+    mut d: serde_json::Map<String, serde_json::Value>,
+) -> serde_json::Map<String, serde_json::Value> {
+    d.retain(|key, _| MINIMAL_KEYS.contains(&key.as_str()));
+    if let Some(image_uris) = d.get_mut("image_uris") {
+        retain_keys(image_uris, &["border_crop"]);
+    }
+    if let Some(faces) = d
+        .get_mut("card_faces")
+        .and_then(|faces| faces.as_array_mut())
+    {
+        for face in faces.iter_mut() {
+            retain_keys(face, &MINIMAL_FACE_KEYS);
+            if let Some(image_uris) = face.get_mut("image_uris") {
+                retain_keys(image_uris, &["border_crop"]);
+            }
+        }
+    }
+    retain_keys_of_all(d.get_mut("all_parts"), &MINIMAL_PART_KEYS);
+    d
+}
+
+const EXTRA_LAYOUTS: [&str; 3] = ["token", "double_faced_token", "emblem"];
+
+/// Whether a scryfall object is a token, emblem, dungeon or other helper card rather than a card.
+pub fn is_extra(d: &serde_json::Map<String, serde_json::Value>) -> bool {
+    let layout = d.get("layout").and_then(|v| v.as_str()).unwrap_or_default();
+    let set_type = d
+        .get("set_type")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let type_line = d
+        .get("type_line")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    EXTRA_LAYOUTS.contains(&layout) || set_type == "token" || type_line.contains("Dungeon")
+}
+
+// art series cards carry the names of the cards whose art they show
+const NON_CARD_LAYOUTS: [&str; 1] = ["art_series"];
+
+/// Whether a scryfall object is a card with a name from the card names catalog of the scryfall
+/// API, so that cards can be told apart from extras in bulk data without asking for the catalog.
+pub fn is_card(d: &serde_json::Map<String, serde_json::Value>) -> bool {
+    let layout = d.get("layout").and_then(|v| v.as_str()).unwrap_or_default();
+    !is_extra(d) && !NON_CARD_LAYOUTS.contains(&layout)
+}
+
+pub fn insert_extra_object(
+    printings: &mut CardPrintings,
+    object: &serde_json::Map<String, serde_json::Value>,
+) {
+    if !is_extra(object) {
+        error!(
+            "couldn't insert scryfall object as an extra because it's a card: {:?}",
+            object.get("name")
+        );
+        return;
+    }
+    let maybe_minimal = MinimalScryfallObject::from_dict(object);
+    match maybe_minimal {
+        Some(minimal) => {
+            let extras = printings.extras.entry(minimal.name.clone()).or_default();
+            if !extras.iter().any(|extra| extra.id == minimal.id) {
+                extras.push(minimal);
+            }
+        }
+        None => {
+            error!("couldn't read {:?} as a scryfall object", object);
+        }
+    }
+}
+
+// scryfall answers a search without results with 404
+async fn query_scryfall_search(
+    uri: &str,
+    client: &ScryfallClient,
+) -> Result<Vec<serde_json::Map<String, serde_json::Value>>> {
+    match client.call(uri).await {
+        Ok(response) => Ok(response.json::<ScryfallSearchAnswer>().await?.data),
+        Err(ClientError::Status(reqwest::StatusCode::NOT_FOUND)) => Ok(Vec::new()),
+        Err(e) => {
+            info!("error in scryfall search request {}: {}", uri, e);
+            Err(e.into())
+        }
+    }
+}
+
+// all pages of the answer, a search for recently released cards can have hundreds of results
+async fn query_scryfall_search_all_pages(
+    uri: &str,
+    client: &ScryfallClient,
+) -> Result<Vec<serde_json::Map<String, serde_json::Value>>> {
+    let mut objects = Vec::new();
+    let mut next_page = Some(uri.to_string());
+    while let Some(uri) = next_page {
+        let answer = match client.call(&uri).await {
+            Ok(response) => response.json::<ScryfallSearchAnswer>().await?,
+            Err(ClientError::Status(reqwest::StatusCode::NOT_FOUND)) => break,
+            Err(e) => {
+                info!("error in scryfall search request {}: {}", uri, e);
+                return Err(e.into());
+            }
+        };
+        objects.extend(answer.data);
+        next_page = answer.next_page.filter(|_| answer.has_more);
+    }
+    Ok(objects)
+}
+
+/// All printings of cards and extras released on or after the date.
+pub async fn query_scryfall_released_since(
+    date: time::Date,
+    client: &ScryfallClient,
+) -> Result<Vec<serde_json::Map<String, serde_json::Value>>> {
+    let uri = client.endpoints().api(&format!(
+        "/cards/search?q=date>={}+include:extras&unique=prints&order=released",
+        date
+    ));
+    query_scryfall_search_all_pages(&uri, client).await
+}
+
+pub async fn query_scryfall_extras_by_name(
     name: &str,
     client: &ScryfallClient,
-) -> Option<Vec<serde_json::Map<String, serde_json::Value>>> {
-    let uri = format!(
-        "https://api.scryfall.com/cards/search?q=name=!{}&unique=prints",
+) -> Result<Vec<serde_json::Map<String, serde_json::Value>>> {
+    let uri = client.endpoints().api(&format!(
+        "/cards/search?q=name=!{}+include:extras&unique=prints",
         encode_card_name(name)
-    );
-    let request = client.call(&uri).await;
-    match request {
-        Ok(response) => match response.json::<ScryfallSearchAnswer>().await {
-            Ok(answer) => Some(answer.data),
-            Err(deserialization_error) => {
-                info!(
-                    "error in deserializing scryfall search request by name: {}",
-                    deserialization_error
-                );
-                None
-            }
-        },
+    ));
+    let objects = query_scryfall_search(&uri, client).await?;
+    Ok(objects.into_iter().filter(is_extra).collect())
+}
+
+pub async fn query_scryfall_by_id(
+    id: &str,
+    client: &ScryfallClient,
+) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let uri = client.endpoints().api(&format!("/cards/{}", id));
+    match client.call(&uri).await {
+        Ok(response) => Ok(response.json().await?),
+        Err(ClientError::Status(reqwest::StatusCode::NOT_FOUND)) => {
+            Err(Error::UnknownCard(id.to_string()))
+        }
         Err(e) => {
-            info!("error in scryfall search request by name: {}", e);
-            None
+            info!("error in scryfall request by id: {}", e);
+            Err(e.into())
         }
     }
 }
 
+pub async fn query_scryfall_by_name(
+    name: &str,
+    client: &ScryfallClient,
+) -> Result<Vec<serde_json::Map<String, serde_json::Value>>> {
+    let uri = client.endpoints().api(&format!(
+        "/cards/search?q=name=!{}&unique=prints",
+        encode_card_name(name)
+    ));
+    query_scryfall_search(&uri, client).await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -235,6 +560,95 @@ mod tests {
             object.meld_result,
             Some("Urza, Planeswalker".to_string().to_lowercase())
         );
+        assert_eq!(object.collector_number.as_deref(), Some("225"));
+        assert_eq!(object.released_at.as_deref(), Some("2022-11-18"));
+        assert_eq!(object.frame.as_deref(), Some("2015"));
+        assert_eq!(object.border_color.as_deref(), Some("black"));
+        assert_eq!(object.layout.as_deref(), Some("meld"));
+        assert_eq!(
+            object.id.as_deref(),
+            Some("8aefe8bd-216a-4ec1-9362-3f9dbf7fd083")
+        );
+        assert_eq!(
+            object.oracle_id.as_deref(),
+            Some("df2af646-3e5b-43a3-8f3e-50565889f456")
+        );
+        assert!(!object.full_art);
+        assert!(!object.promo);
+        assert!(!object.digital);
+    }
+
+    #[test]
+    fn card_data_without_printing_metadata() {
+        let serialized = r#"{"printings":{"shatter":[{"name":"shatter","set":"mrd","language":"en","border_crop":"https://cards.scryfall.io/border_crop/front/a/b/ab.jpg","border_crop_back":null,"meld_result":null}]},"languages":["en"]}"#;
+        let printings = CardPrintings::from_reader(serialized.as_bytes()).unwrap();
+        let shatter = &printings.printings["shatter"][0];
+        assert_eq!(shatter.set, "mrd");
+        assert_eq!(shatter.faces.len(), 1);
+        assert_eq!(shatter.collector_number, None);
+        assert!(!shatter.digital);
+    }
+
+    #[test]
+    fn meld_result_creates_token() {
+        let input = include_str!("../test_input/default-cards_meld.json");
+        let list: Vec<serde_json::Map<String, serde_json::Value>> =
+            serde_json::from_str(input).unwrap();
+        let urza_planeswalker = list
+            .iter()
+            .find(|object| object["name"] == "Urza, Planeswalker")
+            .unwrap();
+        let object = MinimalScryfallObject::from_dict(urza_planeswalker).unwrap();
+        assert_eq!(object.tokens.len(), 1);
+        assert_eq!(object.tokens[0].name, "soldier");
+        assert!(list.iter().all(|object| !is_extra(object)));
+    }
+
+    #[test]
+    fn reduced_objects() {
+        for input in [
+            include_str!("../test_input/default-cards_meld.json"),
+            include_str!("../test_input/default-cards-20200910090351_truncated.json"),
+        ] {
+            let list: Vec<serde_json::Map<String, serde_json::Value>> =
+                serde_json::from_str(input).unwrap();
+            for object in list {
+                let reduced = reduce(object.clone());
+                assert!(reduced.len() < object.len());
+                assert_eq!(
+                    MinimalScryfallObject::from_dict(&reduced),
+                    MinimalScryfallObject::from_dict(&object)
+                );
+                assert_eq!(is_extra(&reduced), is_extra(&object));
+                assert_eq!(is_card(&reduced), is_card(&object));
+            }
+        }
+    }
+
+    #[test]
+    fn spirit_token() {
+        let input = include_str!("../test_input/default-cards-20200910090351_truncated.json");
+        let list: Vec<serde_json::Map<String, serde_json::Value>> =
+            serde_json::from_str(input).unwrap();
+        let extras: Vec<&serde_json::Map<String, serde_json::Value>> =
+            list.iter().filter(|object| is_extra(object)).collect();
+        assert_eq!(extras.len(), 1);
+        assert_eq!(extras[0]["name"], "Spirit");
+        let mut printings = CardPrintings::new();
+        insert_extra_object(&mut printings, extras[0]);
+        insert_extra_object(&mut printings, extras[0]);
+        assert_eq!(printings.extras["spirit"].len(), 1);
+        assert!(printings.printings.is_empty());
+    }
+
+    #[test]
+    fn cards_extras_and_art() {
+        let input = include_str!("../test_input/default-cards-20200910090351_truncated.json");
+        let mut list: Vec<serde_json::Map<String, serde_json::Value>> =
+            serde_json::from_str(input).unwrap();
+        assert_eq!(list.iter().filter(|object| is_card(object)).count(), 4);
+        list[0].insert("layout".to_string(), "art_series".into());
+        assert!(!is_card(&list[0]) && !is_extra(&list[0]));
     }
 
     #[test]
@@ -243,7 +657,35 @@ mod tests {
         let list: Vec<serde_json::Map<String, serde_json::Value>> =
             serde_json::from_str(input).unwrap();
         let object = MinimalScryfallObject::from_dict(&list[0]).unwrap();
-        assert_eq!(object.name, "consecrate // consume")
+        assert_eq!(object.name, "consecrate // consume");
+        assert_eq!(object.faces.len(), 1);
+    }
+
+    #[test]
+    fn legacy_back_face() {
+        let serialized = r#"{"printings":{"kabira takedown // kabira plateau":[{"name":"kabira takedown // kabira plateau","set":"znr","language":"en","border_crop":"https://cards.scryfall.io/border_crop/front/a.jpg","border_crop_back":"https://cards.scryfall.io/border_crop/back/a.jpg","meld_result":null}]},"languages":["en"]}"#;
+        let printings = CardPrintings::from_reader(serialized.as_bytes()).unwrap();
+        let faces = &printings.printings["kabira takedown // kabira plateau"][0].faces;
+        assert_eq!(faces.len(), 2);
+        assert_eq!(
+            faces[1].border_crop,
+            "https://cards.scryfall.io/border_crop/back/a.jpg"
+        );
+    }
+
+    #[test]
+    fn reversible() {
+        let input = include_str!("../test_input/card_data_reversible.json");
+        let list: Vec<serde_json::Map<String, serde_json::Value>> =
+            serde_json::from_str(input).unwrap();
+        let object = MinimalScryfallObject::from_dict(&list[0]).unwrap();
+        assert_eq!(object.name, "zndrsplt, eye of wisdom");
+        assert_eq!(object.faces.len(), 2);
+        assert_ne!(object.faces[0].border_crop, object.faces[1].border_crop);
+        assert_eq!(
+            object.oracle_id.as_deref(),
+            Some("6ae4c7b5-1cbd-4bd4-af8e-4a8c0de4c71e")
+        );
     }
 
     #[test]
@@ -273,4 +715,25 @@ mod tests {
         let object = MinimalScryfallObject::from_dict(&list[0]).unwrap();
         assert_eq!(object.name, "erayo, soratami ascendant // erayo's essence");
     }
+
+    #[test]
+    fn card_names_from_printings() {
+        let mut printings = CardPrintings::new();
+        for name in [
+            "Urza, Lord Protector",
+            "erayo, soratami ascendant // erayo's essence",
+        ] {
+            printings.printings.insert(name.to_string(), Vec::new());
+        }
+        printings.extras.insert("treasure".to_string(), Vec::new());
+        let card_names = ScryfallCardNames::from_printings(&printings);
+        assert_eq!(
+            card_names.names,
+            [
+                "erayo, soratami ascendant // erayo's essence",
+                "urza, lord protector"
+            ]
+        );
+        assert_eq!(card_names.total_values, 2);
+    }
 }
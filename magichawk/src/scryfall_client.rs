@@ -1,52 +1,384 @@
 extern crate reqwest;
 extern crate tokio;
 
+use crate::rate_limit::RateLimiter;
 use lazy_static::lazy_static;
-use log::debug;
+use log::{debug, info, warn};
+use reqwest::StatusCode;
+use reqwest::header::HeaderMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
 use tokio::time::{Duration, Instant};
 
 // headers required according to https://scryfall.com/docs/api/
 const USER_AGENT: &str = "magichawk/0.3";
 const ACCEPT: &str = "*/*";
-const SCRYFALL_COOLDOWN: Duration = Duration::from_millis(100);
+pub const SCRYFALL_COOLDOWN: Duration = Duration::from_millis(100);
+pub const SCRYFALL_API_BASE: &str = "https://api.scryfall.com";
+pub const SCRYFALL_IMAGE_BASE: &str = "https://cards.scryfall.io";
 
-// use a blocking mutex since we are only holding the lock to find out when we can call
+// shared by all clients and blocking calls of the process
 lazy_static! {
-    static ref LAST_SCRYFALL_CALL: std::sync::Mutex<Instant> =
-        std::sync::Mutex::new(Instant::now() - SCRYFALL_COOLDOWN);
+    static ref SCRYFALL_RATE_LIMITER: Arc<RateLimiter> =
+        Arc::new(RateLimiter::new(SCRYFALL_COOLDOWN, 1));
 }
+
+/// The rate limiter used for all calls to the scryfall API unless a client is given its own.
+pub fn scryfall_rate_limiter() -> Arc<RateLimiter> {
+    SCRYFALL_RATE_LIMITER.clone()
+}
+
+/// Where the scryfall API and the card images are served, e.g. by a mirror or a local mock server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScryfallEndpoints {
+    api: String,
+    images: String,
+}
+
+impl Default for ScryfallEndpoints {
+    fn default() -> Self {
+        ScryfallEndpoints::new(SCRYFALL_API_BASE, SCRYFALL_IMAGE_BASE)
+    }
+}
+
+impl ScryfallEndpoints {
+    pub fn new(api: &str, images: &str) -> ScryfallEndpoints {
+        ScryfallEndpoints {
+            api: api.trim_end_matches('/').to_string(),
+            images: images.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// The uri of an API path like `/catalog/card-names`.
+    pub fn api(&self, path: &str) -> String {
+        format!("{}{}", self.api, path)
+    }
+
+    /// The uri to download an image from, the image uris in card data always point to scryfall.
+    pub fn image(&self, uri: &str) -> String {
+        match uri.strip_prefix(SCRYFALL_IMAGE_BASE) {
+            Some(path) if self.images != SCRYFALL_IMAGE_BASE => format!("{}{}", self.images, path),
+            _ => uri.to_string(),
+        }
+    }
+}
+
+/// Why a call to scryfall failed, after retrying where that made sense.
+#[derive(Debug)]
+pub enum ClientError {
+    Network(reqwest::Error),
+    /// the server still asked us to slow down after all retries
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    Status(StatusCode),
+    /// too many failures in a row, calls are rejected until the breaker closes again
+    CircuitOpen,
+}
+
+impl ClientError {
+    /// Whether trying again later might help, as opposed to e.g. asking for a card that doesn't exist.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ClientError::Network(e) => e.is_timeout() || e.is_connect(),
+            ClientError::RateLimited { .. } | ClientError::CircuitOpen => true,
+            ClientError::Status(status) => is_transient_status(*status),
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Network(e) => write!(f, "network error: {}", e),
+            ClientError::RateLimited {
+                retry_after: Some(retry_after),
+            } => write!(f, "rate limited, retry after {:?}", retry_after),
+            ClientError::RateLimited { retry_after: None } => write!(f, "rate limited"),
+            ClientError::Status(status) => write!(f, "unexpected status {}", status),
+            ClientError::CircuitOpen => write!(f, "scryfall seems to be down, not calling it"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Network(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Network(e)
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+// only the delay in seconds, for an http date we fall back to our own backoff
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// How often and how long to wait before calling again after a transient failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    /// upper bound for a single wait, a longer `Retry-After` gives up instead
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with jitter, somewhere between half and all of the exponential delay
+    /// so that clients which failed at the same time don't retry at the same time.
+    fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        // randomly seeded by the standard library, good enough for jitter
+        let random = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        let jitter = exponential.mul_f64((random % 1000) as f64 / 2000.0);
+        exponential - jitter
+    }
+
+    /// How long to wait before the given retry, `None` if we should give up.
+    fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if retry > self.max_retries {
+            return None;
+        }
+        match retry_after {
+            Some(retry_after) if retry_after > self.max_delay => None,
+            Some(retry_after) => Some(retry_after),
+            None => Some(self.backoff(retry - 1)),
+        }
+    }
+}
+
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Stops calling scryfall for a while after several calls in a row failed even with retries,
+/// afterwards a single call is let through to find out whether it is back.
+struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: std::sync::Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    fn new(threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            state: std::sync::Mutex::new(BreakerState {
+                consecutive_failures: 0,
+                open_until: None,
+            }),
+        }
+    }
+
+    fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.open_until {
+            Some(open_until) if Instant::now() < open_until => false,
+            Some(_) => {
+                // half open, keep everybody else out until this call is done
+                state.open_until = Some(Instant::now() + self.cooldown);
+                true
+            }
+            None => true,
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = 0;
+        state.open_until = None;
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.threshold {
+            warn!(
+                "{} failed calls to scryfall in a row, pausing calls for {:?}",
+                state.consecutive_failures, self.cooldown
+            );
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+enum Failure {
+    Retry(ClientError, Option<Duration>),
+    Reject(ClientError),
+}
+
+// None if the response can be handed to the caller
+fn judge_status(status: StatusCode, headers: &HeaderMap) -> Option<Failure> {
+    if status.is_success() || status == StatusCode::NOT_MODIFIED {
+        None
+    } else if status == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = retry_after(headers);
+        Some(Failure::Retry(
+            ClientError::RateLimited { retry_after },
+            retry_after,
+        ))
+    } else if is_transient_status(status) {
+        Some(Failure::Retry(
+            ClientError::Status(status),
+            retry_after(headers),
+        ))
+    } else {
+        Some(Failure::Reject(ClientError::Status(status)))
+    }
+}
+
+fn judge_error(e: reqwest::Error) -> Failure {
+    let e = ClientError::Network(e);
+    if e.is_transient() {
+        Failure::Retry(e, None)
+    } else {
+        Failure::Reject(e)
+    }
+}
+
+fn default_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        reqwest::header::USER_AGENT,
+        reqwest::header::HeaderValue::from_static(USER_AGENT),
+    );
+    headers.insert(
+        reqwest::header::ACCEPT,
+        reqwest::header::HeaderValue::from_static(ACCEPT),
+    );
+    headers
+}
+
 pub struct ScryfallClient {
     client: reqwest::Client,
+    limiter: Arc<RateLimiter>,
+    retry: RetryPolicy,
+    breaker: CircuitBreaker,
+    endpoints: ScryfallEndpoints,
 }
 
 impl ScryfallClient {
     pub fn new() -> ScryfallClient {
-        let mut headers = reqwest::header::HeaderMap::new();
-        headers.insert(
-            reqwest::header::USER_AGENT,
-            reqwest::header::HeaderValue::from_static(USER_AGENT),
-        );
-        headers.insert(
-            reqwest::header::ACCEPT,
-            reqwest::header::HeaderValue::from_static(ACCEPT),
-        );
         ScryfallClient {
             client: reqwest::Client::builder()
-                .default_headers(headers)
+                .default_headers(default_headers())
                 .build()
                 .unwrap(),
+            limiter: scryfall_rate_limiter(),
+            retry: RetryPolicy::default(),
+            breaker: CircuitBreaker::new(5, Duration::from_secs(30)),
+            endpoints: ScryfallEndpoints::default(),
         }
     }
 
-    pub async fn call(&self, uri: &str) -> Result<reqwest::Response, reqwest::Error> {
-        let next_call = {
-            let mut l = *LAST_SCRYFALL_CALL.lock().unwrap();
-            l += SCRYFALL_COOLDOWN;
-            l
-        };
-        tokio::time::sleep_until(next_call).await;
-        debug!("calling scryfall API: {}", uri);
-        self.client.get(uri).send().await
+    pub fn with_rate_limiter(self, limiter: Arc<RateLimiter>) -> ScryfallClient {
+        ScryfallClient { limiter, ..self }
+    }
+
+    pub fn with_retry_policy(self, retry: RetryPolicy) -> ScryfallClient {
+        ScryfallClient { retry, ..self }
+    }
+
+    pub fn with_endpoints(self, endpoints: ScryfallEndpoints) -> ScryfallClient {
+        ScryfallClient { endpoints, ..self }
+    }
+
+    pub fn endpoints(&self) -> &ScryfallEndpoints {
+        &self.endpoints
+    }
+
+    pub async fn call(&self, uri: &str) -> Result<reqwest::Response, ClientError> {
+        self.call_with_headers(uri, HeaderMap::new()).await
+    }
+
+    /// Like `call`, with additional headers for this request only, e.g. for conditional requests.
+    /// Transient failures are retried, the response is only returned for success and 304.
+    pub async fn call_with_headers(
+        &self,
+        uri: &str,
+        headers: HeaderMap,
+    ) -> Result<reqwest::Response, ClientError> {
+        if !self.breaker.allow() {
+            return Err(ClientError::CircuitOpen);
+        }
+        let mut retry = 0;
+        loop {
+            self.limiter.wait().await;
+            debug!("calling scryfall API: {}", uri);
+            let failure = match self.client.get(uri).headers(headers.clone()).send().await {
+                Ok(response) => match judge_status(response.status(), response.headers()) {
+                    None => {
+                        self.breaker.record_success();
+                        return Ok(response);
+                    }
+                    Some(failure) => failure,
+                },
+                Err(e) => judge_error(e),
+            };
+            match failure {
+                Failure::Retry(e, retry_after) => {
+                    retry += 1;
+                    match self.retry.delay(retry, retry_after) {
+                        Some(delay) => {
+                            info!("call to {} failed ({}), retrying in {:?}", uri, e, delay);
+                            tokio::time::sleep(delay).await;
+                        }
+                        None => {
+                            self.breaker.record_failure();
+                            return Err(e);
+                        }
+                    }
+                }
+                Failure::Reject(e) => {
+                    // the server answered, so it is up
+                    self.breaker.record_success();
+                    return Err(e);
+                }
+            }
+        }
     }
 }
 
@@ -56,28 +388,206 @@ impl Default for ScryfallClient {
     }
 }
 
-pub fn blocking_call(uri: &str) -> Result<reqwest::blocking::Response, reqwest::Error> {
-    let next_call = {
-        let mut l = *LAST_SCRYFALL_CALL.lock().unwrap();
-        l += SCRYFALL_COOLDOWN;
-        l
-    };
-    let sleep_interval = next_call - Instant::now();
-    if sleep_interval > Duration::from_secs(0) {
-        std::thread::sleep(sleep_interval);
-    }
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(
-        reqwest::header::USER_AGENT,
-        reqwest::header::HeaderValue::from_static(USER_AGENT),
-    );
-    headers.insert(
-        reqwest::header::ACCEPT,
-        reqwest::header::HeaderValue::from_static(ACCEPT),
-    );
+/// Blocking version of `ScryfallClient::call` for the command line tools, it retries the same
+/// way but has no circuit breaker since nobody else is waiting for it.
+pub fn blocking_call(uri: &str) -> Result<reqwest::blocking::Response, ClientError> {
     let client = reqwest::blocking::Client::builder()
-        .default_headers(headers)
+        .default_headers(default_headers())
         .build()
         .unwrap();
-    client.get(uri).send()
+    let policy = RetryPolicy::default();
+    let mut retry = 0;
+    loop {
+        SCRYFALL_RATE_LIMITER.wait_blocking();
+        let failure = match client.get(uri).send() {
+            Ok(response) => match judge_status(response.status(), response.headers()) {
+                None => return Ok(response),
+                Some(failure) => failure,
+            },
+            Err(e) => judge_error(e),
+        };
+        match failure {
+            Failure::Retry(e, retry_after) => {
+                retry += 1;
+                let delay = policy.delay(retry, retry_after).ok_or(e)?;
+                info!("call to {} failed, retrying in {:?}", uri, delay);
+                std::thread::sleep(delay);
+            }
+            Failure::Reject(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // answers the n-th connection with the n-th response and counts the connections
+    async fn serve(responses: Vec<&'static str>, connections: Arc<AtomicUsize>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                connections.fetch_add(1, Ordering::SeqCst);
+                let mut request = [0u8; 4096];
+                let _ = socket.read(&mut request).await;
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{}/cards/named", address)
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+    const UNAVAILABLE: &str =
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const NOT_FOUND: &str =
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const TOO_MANY: &str = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const TOO_MANY_FOR_LONG: &str = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 3600\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+    fn test_client(max_retries: u32) -> ScryfallClient {
+        ScryfallClient::new()
+            .with_rate_limiter(Arc::new(RateLimiter::new(Duration::ZERO, 1)))
+            .with_retry_policy(RetryPolicy {
+                max_retries,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_secs(1),
+            })
+    }
+
+    #[test]
+    fn backoff_grows_with_jitter() {
+        let policy = RetryPolicy::default();
+        for retry in 0..10 {
+            let exponential = (policy.base_delay * 2u32.pow(retry)).min(policy.max_delay);
+            let backoff = policy.backoff(retry);
+            assert!(backoff <= exponential && backoff >= exponential / 2);
+        }
+        assert_eq!(policy.delay(policy.max_retries + 1, None), None);
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(2))),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(60))), None);
+    }
+
+    #[test]
+    fn parse_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(reqwest::header::RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+        headers.insert(
+            reqwest::header::RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let uri = serve(vec![UNAVAILABLE, TOO_MANY, OK], connections.clone()).await;
+        let response = test_client(3).call(&uri).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_long_retry_after() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let uri = serve(vec![TOO_MANY_FOR_LONG], connections.clone()).await;
+        let error = test_client(3).call(&uri).await.unwrap_err();
+        assert!(matches!(
+            error,
+            ClientError::RateLimited {
+                retry_after: Some(retry_after)
+            } if retry_after == Duration::from_secs(3600)
+        ));
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn endpoints() {
+        let endpoints = ScryfallEndpoints::default();
+        assert_eq!(
+            endpoints.api("/cards/named"),
+            "https://api.scryfall.com/cards/named"
+        );
+        let image = "https://cards.scryfall.io/png/front/8/a/8aefe8bd.png?1670539417";
+        assert_eq!(endpoints.image(image), image);
+        let mirror = ScryfallEndpoints::new("http://localhost:8080/", "http://localhost:8081/");
+        assert_eq!(
+            mirror.api("/cards/named"),
+            "http://localhost:8080/cards/named"
+        );
+        assert_eq!(
+            mirror.image(image),
+            "http://localhost:8081/png/front/8/a/8aefe8bd.png?1670539417"
+        );
+        // images hosted elsewhere are left alone
+        assert_eq!(
+            mirror.image("https://example.com/a.jpg"),
+            "https://example.com/a.jpg"
+        );
+    }
+
+    #[tokio::test]
+    async fn card_names_from_mirror() {
+        const CARD_NAMES: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 62\r\nConnection: close\r\n\r\n{\"object\":\"catalog\",\"uri\":\"\",\"total_values\":1,\"data\":[\"Urza\"]}";
+        let connections = Arc::new(AtomicUsize::new(0));
+        let uri = serve(vec![CARD_NAMES], connections.clone()).await;
+        let base = uri.trim_end_matches("/cards/named");
+        let client = test_client(0).with_endpoints(ScryfallEndpoints::new(base, base));
+        let card_names = crate::ScryfallCardNames::from_api_call(&client)
+            .await
+            .unwrap();
+        assert_eq!(card_names.names, ["urza"]);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let uri = serve(vec![NOT_FOUND], connections.clone()).await;
+        let error = test_client(3).call(&uri).await.unwrap_err();
+        assert!(matches!(error, ClientError::Status(StatusCode::NOT_FOUND)));
+        assert!(!error.is_transient());
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn circuit_breaker_opens() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let uri = serve(vec![UNAVAILABLE; 5], connections.clone()).await;
+        let client = test_client(0);
+        for _ in 0..5 {
+            let error = client.call(&uri).await.unwrap_err();
+            assert!(matches!(
+                error,
+                ClientError::Status(StatusCode::SERVICE_UNAVAILABLE)
+            ));
+        }
+        let error = client.call(&uri).await.unwrap_err();
+        assert!(matches!(error, ClientError::CircuitOpen));
+        assert_eq!(connections.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn circuit_breaker_half_open() {
+        let breaker = CircuitBreaker::new(1, Duration::ZERO);
+        breaker.record_failure();
+        // the cooldown is over immediately, one call is let through
+        assert!(breaker.allow());
+        breaker.record_success();
+        assert!(breaker.allow());
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(3600));
+        breaker.record_failure();
+        assert!(breaker.allow());
+        breaker.record_failure();
+        assert!(!breaker.allow());
+    }
 }
@@ -0,0 +1,429 @@
+use log::{debug, info};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::bulk::{ScryfallObject, read_bulk_file};
+use crate::error::{Error, Result};
+use crate::image_store::{ImageStore, Validators};
+use crate::scryfall::{
+    MinimalScryfallObject, ScryfallCardNames, is_card, is_extra, query_scryfall_by_id,
+    query_scryfall_by_name, query_scryfall_extras_by_name, reduce,
+};
+use crate::scryfall_client::ScryfallClient;
+use crate::{ImageResponse, query_image_conditional};
+
+/// Where card names and the scryfall objects of printings come from.
+#[rocket::async_trait]
+pub trait CardSource: Send + Sync {
+    /// The names of all cards, without tokens, emblems and other helper cards.
+    async fn card_names(&self) -> Result<ScryfallCardNames>;
+    /// All printings of the card with exactly this (lowercase) name.
+    async fn printings_by_name(&self, name: &str) -> Result<Vec<ScryfallObject>>;
+    /// All printings of tokens, emblems and other helper cards with exactly this name.
+    async fn extras_by_name(&self, name: &str) -> Result<Vec<ScryfallObject>>;
+    async fn card_by_id(&self, id: &str) -> Result<ScryfallObject>;
+}
+
+/// Where the bytes of card images come from.
+#[rocket::async_trait]
+pub trait ImageSource: Send + Sync {
+    /// The image at the uri, or `NotModified` if it still matches the validators.
+    async fn image(&self, uri: &str, validators: &Validators) -> Result<ImageResponse>;
+}
+
+#[rocket::async_trait]
+impl CardSource for ScryfallClient {
+    async fn card_names(&self) -> Result<ScryfallCardNames> {
+        ScryfallCardNames::from_api_call(self).await
+    }
+
+    async fn printings_by_name(&self, name: &str) -> Result<Vec<ScryfallObject>> {
+        query_scryfall_by_name(name, self).await
+    }
+
+    async fn extras_by_name(&self, name: &str) -> Result<Vec<ScryfallObject>> {
+        query_scryfall_extras_by_name(name, self).await
+    }
+
+    async fn card_by_id(&self, id: &str) -> Result<ScryfallObject> {
+        query_scryfall_by_id(id, self).await
+    }
+}
+
+#[rocket::async_trait]
+impl ImageSource for ScryfallClient {
+    async fn image(&self, uri: &str, validators: &Validators) -> Result<ImageResponse> {
+        query_image_conditional(uri, validators, self).await
+    }
+}
+
+// lets the card and the image source be the same client
+#[rocket::async_trait]
+impl<T: CardSource + ?Sized> CardSource for Arc<T> {
+    async fn card_names(&self) -> Result<ScryfallCardNames> {
+        self.as_ref().card_names().await
+    }
+
+    async fn printings_by_name(&self, name: &str) -> Result<Vec<ScryfallObject>> {
+        self.as_ref().printings_by_name(name).await
+    }
+
+    async fn extras_by_name(&self, name: &str) -> Result<Vec<ScryfallObject>> {
+        self.as_ref().extras_by_name(name).await
+    }
+
+    async fn card_by_id(&self, id: &str) -> Result<ScryfallObject> {
+        self.as_ref().card_by_id(id).await
+    }
+}
+
+#[rocket::async_trait]
+impl<T: ImageSource + ?Sized> ImageSource for Arc<T> {
+    async fn image(&self, uri: &str, validators: &Validators) -> Result<ImageResponse> {
+        self.as_ref().image(uri, validators).await
+    }
+}
+
+/// Images which have been downloaded before, e.g. by the web server or a prefetch run.
+#[rocket::async_trait]
+impl ImageSource for ImageStore {
+    async fn image(&self, uri: &str, _validators: &Validators) -> Result<ImageResponse> {
+        let (bytes, meta) = self
+            .load(uri)
+            .ok_or_else(|| Error::MissingImage(uri.to_string()))?;
+        Ok(ImageResponse::Modified {
+            bytes,
+            validators: meta.validators,
+        })
+    }
+}
+
+// scryfall objects indexed the way the live API can be queried, reduced to what the card data
+// needs and kept once no matter how they are found
+#[derive(Default)]
+struct CardIndex {
+    objects: Vec<ScryfallObject>,
+    by_name: HashMap<String, Vec<usize>>,
+    by_id: HashMap<String, usize>,
+    card_names: BTreeSet<String>,
+}
+
+// the same normalized name as in the card data, e.g. for reversible cards
+fn index_name(object: &ScryfallObject) -> Option<String> {
+    match MinimalScryfallObject::from_dict(object) {
+        Some(minimal) => Some(minimal.name),
+        None => object
+            .get("name")
+            .and_then(|name| name.as_str())
+            .map(str::to_lowercase),
+    }
+}
+
+impl CardIndex {
+    fn insert(&mut self, object: ScryfallObject) {
+        let Some(name) = index_name(&object) else {
+            return;
+        };
+        if is_card(&object) {
+            self.card_names.insert(name.clone());
+        }
+        let id = object
+            .get("id")
+            .and_then(|id| id.as_str())
+            .map(str::to_string);
+        // e.g. in an older and a newer bulk data file, the one read last is kept
+        if let Some(&position) = id.as_ref().and_then(|id| self.by_id.get(id)) {
+            let old_name = index_name(&self.objects[position]);
+            self.objects[position] = reduce(object);
+            if old_name.as_ref() != Some(&name) {
+                if let Some(old_name) = old_name {
+                    self.remove_name(&old_name, position);
+                }
+                self.by_name.entry(name).or_default().push(position);
+            }
+            return;
+        }
+        let position = self.objects.len();
+        if let Some(id) = id {
+            self.by_id.insert(id, position);
+        }
+        self.by_name.entry(name).or_default().push(position);
+        self.objects.push(reduce(object));
+    }
+
+    // after the object at the position has been replaced by one with another name
+    fn remove_name(&mut self, name: &str, position: usize) {
+        let Some(positions) = self.by_name.get_mut(name) else {
+            return;
+        };
+        positions.retain(|&other| other != position);
+        if !positions.iter().any(|&other| is_card(&self.objects[other])) {
+            self.card_names.remove(name);
+        }
+        if positions.is_empty() {
+            self.by_name.remove(name);
+        }
+    }
+
+    fn card_names(&self) -> ScryfallCardNames {
+        ScryfallCardNames::from_names(self.card_names.iter().cloned().collect())
+    }
+
+    fn by_name(&self, name: &str, extras: bool) -> Vec<ScryfallObject> {
+        self.by_name
+            .get(&name.to_lowercase())
+            .map(|positions| {
+                positions
+                    .iter()
+                    .map(|&position| &self.objects[position])
+                    .filter(|object| is_extra(object) == extras)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn by_id(&self, id: &str) -> Result<ScryfallObject> {
+        self.by_id
+            .get(id)
+            .map(|&position| self.objects[position].clone())
+            .ok_or_else(|| Error::UnknownCard(id.to_string()))
+    }
+}
+
+/// Cards from bulk data files of the scryfall API (see https://scryfall.com/docs/api/bulk-data),
+/// either a single file or all json files in a directory, compressed with gzip or not.
+pub struct BulkDataSource {
+    index: CardIndex,
+}
+
+impl BulkDataSource {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<BulkDataSource> {
+        let path = path.as_ref();
+        let mut index = CardIndex::default();
+        if path.is_dir() {
+            let entries = std::fs::read_dir(path).map_err(|e| {
+                Error::Io(format!(
+                    "couldn't read bulk data directory {:?}: {}",
+                    path, e
+                ))
+            })?;
+            let mut files: Vec<_> = entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.ends_with(".json") || name.ends_with(".json.gz"))
+                })
+                .collect();
+            files.sort();
+            for file in files {
+                info!("reading bulk data from {:?}", file);
+                read_bulk_file(&file, |object| index.insert(object))?;
+            }
+        } else {
+            info!("reading bulk data from {:?}", path);
+            read_bulk_file(path, |object| index.insert(object))?;
+        }
+        debug!("{} card names in bulk data", index.card_names.len());
+        Ok(BulkDataSource { index })
+    }
+}
+
+#[rocket::async_trait]
+impl CardSource for BulkDataSource {
+    async fn card_names(&self) -> Result<ScryfallCardNames> {
+        Ok(self.index.card_names())
+    }
+
+    async fn printings_by_name(&self, name: &str) -> Result<Vec<ScryfallObject>> {
+        Ok(self.index.by_name(name, false))
+    }
+
+    async fn extras_by_name(&self, name: &str) -> Result<Vec<ScryfallObject>> {
+        Ok(self.index.by_name(name, true))
+    }
+
+    async fn card_by_id(&self, id: &str) -> Result<ScryfallObject> {
+        self.index.by_id(id)
+    }
+}
+
+/// Cards and images held in memory, for tests which shouldn't depend on the network.
+#[derive(Default)]
+pub struct FixtureSource {
+    index: CardIndex,
+    images: HashMap<String, Vec<u8>>,
+}
+
+impl FixtureSource {
+    pub fn new() -> FixtureSource {
+        FixtureSource::default()
+    }
+
+    pub fn with_cards<I: IntoIterator<Item = ScryfallObject>>(mut self, objects: I) -> Self {
+        for object in objects {
+            self.index.insert(object);
+        }
+        self
+    }
+
+    pub fn with_image(mut self, uri: &str, bytes: Vec<u8>) -> Self {
+        self.images.insert(uri.to_string(), bytes);
+        self
+    }
+}
+
+#[rocket::async_trait]
+impl CardSource for FixtureSource {
+    async fn card_names(&self) -> Result<ScryfallCardNames> {
+        Ok(self.index.card_names())
+    }
+
+    async fn printings_by_name(&self, name: &str) -> Result<Vec<ScryfallObject>> {
+        Ok(self.index.by_name(name, false))
+    }
+
+    async fn extras_by_name(&self, name: &str) -> Result<Vec<ScryfallObject>> {
+        Ok(self.index.by_name(name, true))
+    }
+
+    async fn card_by_id(&self, id: &str) -> Result<ScryfallObject> {
+        self.index.by_id(id)
+    }
+}
+
+#[rocket::async_trait]
+impl ImageSource for FixtureSource {
+    async fn image(&self, uri: &str, _validators: &Validators) -> Result<ImageResponse> {
+        let bytes = self
+            .images
+            .get(uri)
+            .ok_or_else(|| Error::MissingImage(uri.to_string()))?;
+        Ok(ImageResponse::Modified {
+            bytes: bytes.clone(),
+            validators: Validators::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meld_objects() -> Vec<ScryfallObject> {
+        serde_json::from_str(include_str!("../test_input/default-cards_meld.json")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn fixture_cards() {
+        let source = FixtureSource::new().with_cards(meld_objects());
+        let names = source.card_names().await.unwrap();
+        assert_eq!(
+            names.names,
+            [
+                "the mightstone and weakstone",
+                "urza, lord protector",
+                "urza, planeswalker"
+            ]
+        );
+        let printings = source
+            .printings_by_name("Urza, Lord Protector")
+            .await
+            .unwrap();
+        assert_eq!(printings.len(), 4);
+        assert!(
+            source
+                .extras_by_name("urza, lord protector")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let by_id = source
+            .card_by_id("02aea379-b444-46a3-82f4-3038f698d4f4")
+            .await
+            .unwrap();
+        assert_eq!(by_id["name"], "The Mightstone and Weakstone");
+        assert!(matches!(
+            source.card_by_id("nonsense").await,
+            Err(Error::UnknownCard(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn bulk_data_file_or_directory() {
+        let dir = std::env::temp_dir().join(format!("magichawk-bulk-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("default-cards.json"),
+            include_str!("../test_input/default-cards_meld.json"),
+        )
+        .unwrap();
+        // the same printings again, as in an older dump next to a newer one
+        std::fs::write(
+            dir.join("default-cards-old.json"),
+            include_str!("../test_input/default-cards_meld.json"),
+        )
+        .unwrap();
+        std::fs::write(dir.join("notes.txt"), "not bulk data").unwrap();
+        let source = BulkDataSource::from_path(&dir).unwrap();
+        assert_eq!(source.card_names().await.unwrap().names.len(), 3);
+        let file = BulkDataSource::from_path(dir.join("default-cards.json")).unwrap();
+        assert_eq!(file.card_names().await.unwrap().names.len(), 3);
+        assert!(matches!(
+            BulkDataSource::from_path(dir.join("missing.json")),
+            Err(Error::Io(_))
+        ));
+        assert_eq!(
+            source
+                .printings_by_name("urza, planeswalker")
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn renamed_printing() {
+        let mightstone = meld_objects().remove(0);
+        let mut renamed = mightstone.clone();
+        renamed.insert("name".to_string(), "The Mightstone".into());
+        let source = FixtureSource::new().with_cards([mightstone, renamed]);
+        assert!(
+            source
+                .printings_by_name("the mightstone and weakstone")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            source
+                .printings_by_name("the mightstone")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(source.card_names().await.unwrap().names, ["the mightstone"]);
+    }
+
+    #[tokio::test]
+    async fn fixture_images() {
+        let source = FixtureSource::new().with_image("a", vec![1, 2, 3]);
+        assert_eq!(
+            source.image("a", &Validators::default()).await.unwrap(),
+            ImageResponse::Modified {
+                bytes: vec![1, 2, 3],
+                validators: Validators::default()
+            }
+        );
+        assert!(matches!(
+            source.image("b", &Validators::default()).await,
+            Err(Error::MissingImage(_))
+        ));
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::merge::printing_key;
+use crate::scryfall::{CardPrintings, MinimalScryfallObject, get_minimal_scryfall_languages};
+
+// layouts with an image for each side of the card
+const DOUBLE_FACED_LAYOUTS: [&str; 4] = [
+    "transform",
+    "modal_dfc",
+    "double_faced_token",
+    "reversible_card",
+];
+
+/// Something wrong with a printing in card data, e.g. because the card data file was written by a
+/// broken version of create_card_data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// the same printing, by scryfall id, is stored more than once
+    DuplicatePrinting {
+        name: String,
+        set: String,
+    },
+    MissingFaces {
+        name: String,
+        set: String,
+    },
+    MissingBackFace {
+        name: String,
+        set: String,
+        layout: String,
+    },
+    /// the card melds into a card without printings
+    DanglingMeldResult {
+        name: String,
+        set: String,
+        meld_result: String,
+    },
+    UnknownLanguage {
+        name: String,
+        set: String,
+        language: String,
+    },
+    InvalidUri {
+        name: String,
+        set: String,
+        uri: String,
+    },
+}
+
+impl Problem {
+    /// A short description of the kind of problem, to count them.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Problem::DuplicatePrinting { .. } => "duplicate printing",
+            Problem::MissingFaces { .. } => "missing faces",
+            Problem::MissingBackFace { .. } => "missing back face",
+            Problem::DanglingMeldResult { .. } => "dangling meld result",
+            Problem::UnknownLanguage { .. } => "unknown language",
+            Problem::InvalidUri { .. } => "invalid uri",
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::DuplicatePrinting { name, set } => {
+                write!(f, "{} ({}) is stored more than once", name, set)
+            }
+            Problem::MissingFaces { name, set } => write!(f, "{} ({}) has no images", name, set),
+            Problem::MissingBackFace { name, set, layout } => write!(
+                f,
+                "{} ({}) has the layout {} but no back face",
+                name, set, layout
+            ),
+            Problem::DanglingMeldResult {
+                name,
+                set,
+                meld_result,
+            } => write!(
+                f,
+                "{} ({}) melds into {} which has no printings",
+                name, set, meld_result
+            ),
+            Problem::UnknownLanguage {
+                name,
+                set,
+                language,
+            } => write!(
+                f,
+                "{} ({}) has the unknown language {}",
+                name, set, language
+            ),
+            Problem::InvalidUri { name, set, uri } => {
+                write!(f, "{} ({}) has the invalid image uri {}", name, set, uri)
+            }
+        }
+    }
+}
+
+/// How many cards there are in card data, by set and by language.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CardDataStats {
+    pub names: usize,
+    pub printings: usize,
+    pub extra_names: usize,
+    pub extra_printings: usize,
+    /// printings of cards and extras
+    pub by_set: BTreeMap<String, usize>,
+    pub by_language: BTreeMap<String, usize>,
+}
+
+fn is_valid_uri(uri: &str) -> bool {
+    reqwest::Url::parse(uri)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some())
+}
+
+fn check_printing(
+    printing: &MinimalScryfallObject,
+    printings: &CardPrintings,
+    languages: &HashSet<String>,
+    problems: &mut Vec<Problem>,
+) {
+    let name = || printing.name.clone();
+    let set = || printing.set.clone();
+    if printing.faces.is_empty() {
+        problems.push(Problem::MissingFaces {
+            name: name(),
+            set: set(),
+        });
+    }
+    if let Some(layout) = &printing.layout
+        && DOUBLE_FACED_LAYOUTS.contains(&layout.as_str())
+        && printing.faces.len() == 1
+    {
+        problems.push(Problem::MissingBackFace {
+            name: name(),
+            set: set(),
+            layout: layout.clone(),
+        });
+    }
+    if let Some(meld_result) = &printing.meld_result
+        && !printings.printings.contains_key(meld_result)
+    {
+        problems.push(Problem::DanglingMeldResult {
+            name: name(),
+            set: set(),
+            meld_result: meld_result.clone(),
+        });
+    }
+    if !languages.contains(&printing.language) {
+        problems.push(Problem::UnknownLanguage {
+            name: name(),
+            set: set(),
+            language: printing.language.clone(),
+        });
+    }
+    for face in printing.faces.iter() {
+        if !is_valid_uri(&face.border_crop) {
+            problems.push(Problem::InvalidUri {
+                name: name(),
+                set: set(),
+                uri: face.border_crop.clone(),
+            });
+        }
+    }
+}
+
+impl CardPrintings {
+    fn all_printings(&self) -> impl Iterator<Item = &MinimalScryfallObject> {
+        self.printings
+            .values()
+            .chain(self.extras.values())
+            .flatten()
+    }
+
+    /// All problems of the printings of cards and extras.
+    pub fn validate(&self) -> Vec<Problem> {
+        // scryfall adds languages now and then, the card data knows those of its printings
+        let mut languages = get_minimal_scryfall_languages();
+        languages.extend(self.languages.iter().cloned());
+        let mut problems = Vec::new();
+        let mut keys = HashSet::new();
+        for printing in self.all_printings() {
+            if !keys.insert(printing_key(printing)) {
+                problems.push(Problem::DuplicatePrinting {
+                    name: printing.name.clone(),
+                    set: printing.set.clone(),
+                });
+            }
+            check_printing(printing, self, &languages, &mut problems);
+        }
+        problems
+    }
+
+    pub fn stats(&self) -> CardDataStats {
+        let mut stats = CardDataStats {
+            names: self.printings.len(),
+            printings: self.printings.values().map(|list| list.len()).sum(),
+            extra_names: self.extras.len(),
+            extra_printings: self.extras.values().map(|list| list.len()).sum(),
+            ..CardDataStats::default()
+        };
+        for printing in self.all_printings() {
+            *stats.by_set.entry(printing.set.clone()).or_default() += 1;
+            *stats
+                .by_language
+                .entry(printing.language.clone())
+                .or_default() += 1;
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bulk::ScryfallObject;
+
+    fn meld_printings() -> CardPrintings {
+        let objects: Vec<ScryfallObject> =
+            serde_json::from_str(include_str!("../test_input/default-cards_meld.json")).unwrap();
+        let mut printings = CardPrintings::new();
+        for minimal in objects.iter().filter_map(MinimalScryfallObject::from_dict) {
+            printings
+                .printings
+                .entry(minimal.name.clone())
+                .or_default()
+                .push(minimal);
+        }
+        printings
+    }
+
+    #[test]
+    fn valid_card_data() {
+        let printings = meld_printings();
+        assert_eq!(printings.validate(), []);
+        let stats = printings.stats();
+        assert_eq!((stats.names, stats.printings), (3, 10));
+        assert_eq!(stats.by_set["pbro"], 6);
+        assert_eq!(stats.by_language["en"], 10);
+    }
+
+    #[test]
+    fn recorded_language() {
+        let mut printings = meld_printings();
+        let urza = printings.printings.get_mut("urza, lord protector").unwrap();
+        urza[0].language = "qya".to_string();
+        assert_eq!(printings.validate().len(), 1);
+        printings.languages.insert("qya".to_string());
+        assert_eq!(printings.validate(), []);
+    }
+
+    #[test]
+    fn broken_card_data() {
+        let mut printings = meld_printings();
+        printings.printings.remove("urza, planeswalker");
+        let urza = printings.printings.get_mut("urza, lord protector").unwrap();
+        urza.push(urza[0].clone());
+        let mut broken = urza[0].clone();
+        broken.id = Some("broken".to_string());
+        broken.meld_result = None;
+        broken.layout = Some("transform".to_string());
+        broken.language = "xx".to_string();
+        broken.faces[0].border_crop = "border_crop/front/8/a/8aefe8bd.jpg".to_string();
+        urza.push(broken);
+
+        let problems = printings.validate();
+        let kinds: HashSet<&str> = problems.iter().map(|problem| problem.kind()).collect();
+        assert_eq!(
+            kinds,
+            HashSet::from([
+                "duplicate printing",
+                "missing back face",
+                "dangling meld result",
+                "unknown language",
+                "invalid uri"
+            ])
+        );
+        // every printing of a card which melds into the removed card
+        let dangling = problems
+            .iter()
+            .filter(|problem| matches!(problem, Problem::DanglingMeldResult { .. }))
+            .count();
+        assert_eq!(dangling, 7);
+        assert_eq!(problems.len(), 11);
+    }
+}